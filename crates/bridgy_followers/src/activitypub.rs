@@ -0,0 +1,39 @@
+use color_eyre::eyre::{Result, WrapErr};
+use reqwest::{Client, header::ACCEPT};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// An ActivityPub actor, with only the fields linking it to other identities
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Actor {
+    pub id: String,
+    /// Other identities of the actor, Bridgy Fed lists the `at://` URI of the Bluesky account here
+    #[serde(default)]
+    pub also_known_as: Vec<String>,
+}
+
+impl Actor {
+    /// The id of the actor followed by its other identities
+    pub fn identities(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.id.as_str()).chain(self.also_known_as.iter().map(String::as_str))
+    }
+}
+
+/// Fetch an ActivityPub actor from its id (e.g. the `uri` of a Mastodon account)
+#[instrument(skip(client))]
+pub async fn fetch_actor(client: &Client, actor_id: &str) -> Result<Actor> {
+    debug!("Fetching ActivityPub actor: {}", actor_id);
+    client
+        .get(actor_id)
+        .header(ACCEPT, ACTIVITY_JSON)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .wrap_err_with(|| format!("Failed to fetch ActivityPub actor {actor_id}"))?
+        .json()
+        .await
+        .wrap_err_with(|| format!("Invalid ActivityPub actor {actor_id}"))
+}
@@ -0,0 +1,328 @@
+use atrium_api::types::string::Did;
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail, eyre},
+};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, info, instrument};
+use url::Url;
+
+/// AppView used to resolve handles that can't be resolved with HTTPS when no AppView is configured
+const DEFAULT_HANDLE_RESOLVER_URL: &str = "https://public.api.bsky.app";
+
+const PDS_SERVICE_ID: &str = "#atproto_pds";
+
+const USER_AGENT: &str = "bridgy_followers";
+
+/// A service of an atproto identity, e.g. `did:web:api.bsky.app#bsky_appview`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceRef {
+    pub did: Did,
+    /// The id of the service in the DID document, without the leading `#`
+    pub service_id: String,
+}
+
+impl ServiceRef {
+    pub fn parse(value: &str) -> Result<Self> {
+        let Some((did, service_id)) = value.split_once('#') else {
+            bail!("'{value}' is not a service reference, expected a DID followed by #service_id");
+        };
+        let did = Did::new(did.to_string()).map_err(|e| eyre!("Invalid DID '{did}': {e}"))?;
+        Ok(Self {
+            did,
+            service_id: service_id.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
+    id: String,
+    /// Other identifiers of the account, including its handle as an `at://` URI
+    #[serde(default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
+    service: Vec<DidService>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidService {
+    id: String,
+    service_endpoint: String,
+}
+
+impl DidDocument {
+    /// The endpoint of a service, its id may be relative to the document (`#atproto_pds`) or absolute
+    fn service_endpoint(&self, service_id: &str) -> Option<&str> {
+        let absolute_id = format!("{}{service_id}", self.id);
+        self.service
+            .iter()
+            .find(|service| service.id == service_id || service.id == absolute_id)
+            .map(|service| service.service_endpoint.as_str())
+    }
+
+    /// Whether the document claims the handle back, which is what makes a handle resolution trustworthy
+    fn claims_handle(&self, handle: &str) -> bool {
+        self.also_known_as.iter().any(|identifier| {
+            identifier
+                .strip_prefix("at://")
+                .is_some_and(|claimed| claimed.eq_ignore_ascii_case(handle))
+        })
+    }
+
+    /// The URL of a service of the identity (e.g. the PDS of an account, or an AppView)
+    fn service_url(&self, service_id: &str) -> Result<Url> {
+        let endpoint = self
+            .service_endpoint(service_id)
+            .ok_or_else(|| eyre!("No {service_id} service in the DID document of {}", self.id))?;
+        Url::parse(endpoint).wrap_err_with(|| format!("Invalid service endpoint '{endpoint}'"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveHandleOutput {
+    did: String,
+}
+
+/// Resolves handles and DIDs to the services hosting them, as described in the [identity spec][identity]
+///
+/// [identity]: https://atproto.com/specs/did
+pub struct IdentityResolver {
+    client: Client,
+    plc_directory_url: Url,
+    appview: Option<ServiceRef>,
+}
+
+impl IdentityResolver {
+    pub fn new(plc_directory_url: &str, appview: Option<ServiceRef>) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(30))
+            .build()
+            .wrap_err("Failed to create identity HTTP client")?;
+        let plc_directory_url = Url::parse(plc_directory_url)
+            .wrap_err_with(|| format!("Invalid PLC directory URL '{plc_directory_url}'"))?;
+
+        Ok(Self {
+            client,
+            plc_directory_url,
+            appview,
+        })
+    }
+
+    /// The URL of a did:plc document, under the path of the directory if it has one. `Url::join` can't be used: it
+    /// would read the DID as an absolute URL with a `did` scheme, and drop the last segment of the path.
+    fn plc_document_url(&self, did: &Did) -> Result<Url> {
+        let directory = self.plc_directory_url.as_str().trim_end_matches('/');
+        Url::parse(&format!("{directory}/{}", did.as_str()))
+            .wrap_err_with(|| format!("Invalid PLC directory URL for {}", did.as_str()))
+    }
+
+    /// Fetch the DID document of a did:plc from the PLC directory, or of a did:web from its host
+    #[instrument(skip(self))]
+    async fn resolve_did_document(&self, did: &Did) -> Result<DidDocument> {
+        let url = if did.as_str().starts_with("did:plc:") {
+            self.plc_document_url(did)?
+        } else if let Some(id) = did.as_str().strip_prefix("did:web:") {
+            // The host may contain an encoded port and be followed by a path, e.g. did:web:localhost%3A2583
+            let mut parts = id.split(':');
+            let host = parts.next().unwrap_or_default().replace("%3A", ":");
+            let path: Vec<_> = parts.collect();
+            if path.is_empty() {
+                Url::parse(&format!("https://{host}/.well-known/did.json"))?
+            } else {
+                Url::parse(&format!("https://{host}/{}/did.json", path.join("/")))?
+            }
+        } else {
+            bail!("Unsupported DID method for '{did}'", did = did.as_str());
+        };
+
+        debug!("Fetching DID document from {url}");
+        let document: DidDocument = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err_with(|| format!("Failed to fetch the DID document of {}", did.as_str()))?
+            .json()
+            .await
+            .wrap_err_with(|| format!("Invalid DID document for {}", did.as_str()))?;
+
+        if document.id != did.as_str() {
+            bail!(
+                "DID document of {} is for another DID ({})",
+                did.as_str(),
+                document.id
+            );
+        }
+
+        Ok(document)
+    }
+
+    /// The endpoint of a service of an identity (e.g. the PDS of an account, or an AppView)
+    async fn resolve_service(&self, did: &Did, service_id: &str) -> Result<Url> {
+        self.resolve_did_document(did)
+            .await?
+            .service_url(service_id)
+    }
+
+    /// Resolve a handle to a DID with the HTTPS well-known method, falling back to the AppView for handles only
+    /// verified with DNS
+    #[instrument(skip(self))]
+    pub async fn resolve_handle(&self, handle: &str) -> Result<Did> {
+        let handle = handle.trim_start_matches('@');
+
+        let well_known = format!("https://{handle}/.well-known/atproto-did");
+        match self.client.get(&well_known).send().await {
+            Ok(response) if response.status().is_success() => {
+                let text = response.text().await?;
+                if let Ok(did) = Did::new(text.trim().to_string()) {
+                    return Ok(did);
+                }
+            }
+            Ok(response) => debug!("{well_known} returned {}", response.status()),
+            Err(e) => debug!("Failed to fetch {well_known}: {e}"),
+        }
+
+        let resolver_url = match &self.appview {
+            Some(appview) => {
+                self.resolve_service(&appview.did, &format!("#{}", appview.service_id))
+                    .await?
+            }
+            None => Url::parse(DEFAULT_HANDLE_RESOLVER_URL)?,
+        };
+        let mut url = resolver_url.join("/xrpc/com.atproto.identity.resolveHandle")?;
+        url.query_pairs_mut().append_pair("handle", handle);
+
+        let output: ResolveHandleOutput = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err_with(|| format!("Failed to resolve handle {handle}"))?
+            .json()
+            .await?;
+        Did::new(output.did).map_err(|e| eyre!("Invalid DID for handle {handle}: {e}"))
+    }
+
+    /// Find the DID of an account, given by handle or DID, and the URL of the PDS hosting it.
+    ///
+    /// The DID document must claim a handle back, otherwise whoever answered the handle resolution (e.g. a hostile
+    /// AppView) could send the credentials to a PDS of their choice.
+    pub async fn resolve_pds(&self, identifier: &str) -> Result<(Did, Url)> {
+        let identifier = identifier.trim_start_matches('@');
+        let (did, handle) = if identifier.starts_with("did:") {
+            let did = Did::new(identifier.to_string())
+                .map_err(|e| eyre!("Invalid DID '{identifier}': {e}"))?;
+            (did, None)
+        } else {
+            (self.resolve_handle(identifier).await?, Some(identifier))
+        };
+
+        let document = self.resolve_did_document(&did).await?;
+        if let Some(handle) = handle
+            && !document.claims_handle(handle)
+        {
+            bail!(
+                "The handle {handle} resolves to {} but its DID document doesn't claim the handle",
+                did.as_str()
+            );
+        }
+        let pds = document.service_url(PDS_SERVICE_ID)?;
+        info!(
+            did = did.as_str(),
+            "Account '{identifier}' is hosted on {pds}"
+        );
+        Ok((did, pds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Response};
+    use serde_json::json;
+
+    const APPVIEW_DID: &str = "did:plc:appview";
+    const ACCOUNT_DID: &str = "did:plc:alice";
+    // The reserved .invalid TLD makes the HTTPS resolution fail and fall back to the AppView
+    const HANDLE: &str = "alice.invalid";
+
+    /// A PLC directory under `/plc`, also acting as the AppView, whose account claims `claimed_handle`
+    async fn directory(claimed_handle: &'static str) -> String {
+        let base = std::sync::Arc::new(std::sync::OnceLock::<String>::new());
+        let server_base = base.clone();
+        let url = test_server::start(move |request| {
+            let base = server_base.get().unwrap();
+            let document = match request.path() {
+                "/plc/did:plc:appview" => json!({
+                    "id": APPVIEW_DID,
+                    "service": [{ "id": "#bsky_appview", "type": "BskyAppView", "serviceEndpoint": base }],
+                }),
+                "/plc/did:plc:alice" => json!({
+                    "id": ACCOUNT_DID,
+                    "alsoKnownAs": [format!("at://{claimed_handle}")],
+                    "service": [{ "id": "#atproto_pds", "type": "AtprotoPersonalDataServer", "serviceEndpoint": "https://pds.example.com" }],
+                }),
+                "/xrpc/com.atproto.identity.resolveHandle" => json!({ "did": ACCOUNT_DID }),
+                _ => return Some(Response::new(404)),
+            };
+            Some(Response::json(200, &document))
+        })
+        .await;
+        base.set(url.clone()).unwrap();
+        url
+    }
+
+    fn resolver(directory: &str) -> IdentityResolver {
+        let appview = ServiceRef::parse(&format!("{APPVIEW_DID}#bsky_appview")).unwrap();
+        IdentityResolver::new(&format!("{directory}/plc"), Some(appview)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolves_pds_of_claimed_handle() {
+        let directory = directory("Alice.invalid").await;
+
+        let (did, pds) = resolver(&directory).resolve_pds(HANDLE).await.unwrap();
+
+        assert_eq!(did.as_str(), ACCOUNT_DID);
+        assert_eq!(pds.as_str(), "https://pds.example.com/");
+    }
+
+    #[tokio::test]
+    async fn rejects_handle_not_claimed_by_did() {
+        let directory = directory("mallory.invalid").await;
+
+        let result = resolver(&directory).resolve_pds(HANDLE).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn resolves_did_without_handle_check() {
+        let directory = directory("mallory.invalid").await;
+
+        let (did, _) = resolver(&directory).resolve_pds(ACCOUNT_DID).await.unwrap();
+
+        assert_eq!(did.as_str(), ACCOUNT_DID);
+    }
+
+    #[test]
+    fn keeps_path_of_plc_directory() {
+        let did = Did::new(ACCOUNT_DID.to_string()).unwrap();
+        for directory in ["https://example.com/plc", "https://example.com/plc/"] {
+            let resolver = IdentityResolver::new(directory, None).unwrap();
+
+            assert_eq!(
+                resolver.plc_document_url(&did).unwrap().as_str(),
+                "https://example.com/plc/did:plc:alice"
+            );
+        }
+    }
+}
@@ -1,5 +1,12 @@
+mod identity;
+mod oauth;
+mod session;
 mod tui;
 mod utils;
 
-pub use tui::authenticate;
-pub use utils::{BlueskyAgent, get_bridgy_did, get_known_followers, get_relationships};
+pub use tui::{authenticate, ensure_session, resolve_handle_without_login};
+pub use utils::{
+    BlueskyAgent, follow_account, get_blocks, get_bridgy_did, get_followers, get_follows,
+    get_known_followers, get_list_members, get_mutes, get_own_did, get_profiles, get_relationships,
+    get_starter_pack_list, resolve_handle,
+};
@@ -0,0 +1,685 @@
+use super::identity::IdentityResolver;
+use crate::loopback;
+use atrium_api::types::string::Did;
+use atrium_common::store::Store;
+use atrium_identity::{
+    did::{CommonDidResolver, CommonDidResolverConfig},
+    handle::{AtprotoHandleResolver, AtprotoHandleResolverConfig, DnsTxtResolver},
+};
+use atrium_oauth::{
+    AtprotoLocalhostClientMetadata, AuthorizeOptions, CallbackParams, DefaultHttpClient,
+    KnownScope, OAuthClient, OAuthClientConfig, OAuthResolverConfig, OAuthSession, Scope,
+    store::{
+        session::{Session, SessionStore},
+        state::MemoryStateStore,
+    },
+};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail, eyre},
+};
+use keyring::Credential;
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+use tracing::info;
+
+const CALLBACK_PATH: &str = "/callback";
+
+/// How long to wait for the user to authorize the application in their browser
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+type DidResolver = CommonDidResolver<DefaultHttpClient>;
+type HandleResolver = AtprotoHandleResolver<NoDnsTxtResolver, DefaultHttpClient>;
+type BlueskyOAuthClient =
+    OAuthClient<MemoryStateStore, CredentialSessionStore, DidResolver, HandleResolver>;
+
+pub type OAuthBlueskySession =
+    OAuthSession<DefaultHttpClient, DidResolver, HandleResolver, CredentialSessionStore>;
+
+/// Handles are resolved to a DID by `IdentityResolver` before starting the flow, falling back to the AppView so that
+/// handles verified with DNS work without a DNS client. The resolver of `atrium-oauth` is only left with HTTPS.
+pub struct NoDnsTxtResolver;
+
+impl DnsTxtResolver for NoDnsTxtResolver {
+    async fn resolve(
+        &self,
+        _query: &str,
+    ) -> core::result::Result<Vec<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Stores the OAuth session (the DPoP key and the token set) of a single account in the credential store, refreshed
+/// tokens are saved there too.
+pub struct CredentialSessionStore {
+    credential: Box<Credential>,
+    /// The account the sessions must be for, if known
+    account: Option<Did>,
+}
+
+impl CredentialSessionStore {
+    pub fn new(credential: Box<Credential>) -> Self {
+        Self {
+            credential,
+            account: None,
+        }
+    }
+
+    /// A store refusing the sessions of other accounts, so that an authorization server can't log in to another
+    /// account than the configured one
+    fn for_account(credential: Box<Credential>, account: Did) -> Self {
+        Self {
+            credential,
+            account: Some(account),
+        }
+    }
+
+    fn load(&self) -> keyring::Result<Option<Session>> {
+        match self.credential.get_password() {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| keyring::Error::Invalid("oauth_session".to_string(), e.to_string())),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The DID of the account of the stored session, if any
+    fn did(&self) -> Result<Option<Did>> {
+        let Some(session) = self.load()? else {
+            return Ok(None);
+        };
+        let did = Did::new(session.token_set.sub.clone())
+            .map_err(|e| eyre!("Invalid DID in the stored OAuth session: {e}"))?;
+        Ok(Some(did))
+    }
+}
+
+impl Store<Did, Session> for CredentialSessionStore {
+    type Error = keyring::Error;
+
+    async fn get(&self, key: &Did) -> core::result::Result<Option<Session>, Self::Error> {
+        Ok(self
+            .load()?
+            .filter(|session| session.token_set.sub == key.as_str()))
+    }
+
+    async fn set(&self, _key: Did, value: Session) -> core::result::Result<(), Self::Error> {
+        if let Some(account) = &self.account
+            && value.token_set.sub != account.as_str()
+        {
+            return Err(keyring::Error::Invalid(
+                "oauth_session".to_string(),
+                format!(
+                    "the session is for {} instead of {}",
+                    value.token_set.sub,
+                    account.as_str()
+                ),
+            ));
+        }
+        let json = serde_json::to_string(&value)
+            .map_err(|e| keyring::Error::Invalid("oauth_session".to_string(), e.to_string()))?;
+        self.credential.set_password(&json)
+    }
+
+    async fn del(&self, _key: &Did) -> core::result::Result<(), Self::Error> {
+        self.clear().await
+    }
+
+    async fn clear(&self) -> core::result::Result<(), Self::Error> {
+        match self.credential.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl SessionStore for CredentialSessionStore {}
+
+/// The loopback redirect. Its port is part of the client id of native apps, so a session can only be refreshed with
+/// the port it was authorized with.
+fn redirect_uri(callback_port: u16) -> String {
+    format!("http://127.0.0.1:{callback_port}{CALLBACK_PATH}")
+}
+
+/// Scopes needed to read the social graph and follow accounts
+fn scopes() -> Vec<Scope> {
+    vec![
+        Scope::Known(KnownScope::Atproto),
+        Scope::Known(KnownScope::TransitionGeneric),
+    ]
+}
+
+fn create_client(
+    session_store: CredentialSessionStore,
+    plc_directory_url: &str,
+    callback_port: u16,
+) -> Result<BlueskyOAuthClient> {
+    let http_client = Arc::new(DefaultHttpClient::default());
+    let config = OAuthClientConfig {
+        client_metadata: AtprotoLocalhostClientMetadata {
+            redirect_uris: Some(vec![redirect_uri(callback_port)]),
+            scopes: Some(scopes()),
+        },
+        keys: None,
+        resolver: OAuthResolverConfig {
+            did_resolver: CommonDidResolver::new(CommonDidResolverConfig {
+                plc_directory_url: plc_directory_url.to_string(),
+                http_client: Arc::clone(&http_client),
+            }),
+            handle_resolver: AtprotoHandleResolver::new(AtprotoHandleResolverConfig {
+                dns_txt_resolver: NoDnsTxtResolver,
+                http_client: Arc::clone(&http_client),
+            }),
+            authorization_server_metadata: Default::default(),
+            protected_resource_metadata: Default::default(),
+        },
+        state_store: MemoryStateStore::default(),
+        session_store,
+    };
+
+    OAuthClient::new(config).map_err(|e| eyre!("Failed to create OAuth client: {e}"))
+}
+
+/// Restore the session stored in the credential store, refreshing its tokens if needed. Returns `None` if no session
+/// is stored.
+pub async fn restore(
+    credential: Box<Credential>,
+    plc_directory_url: &str,
+    callback_port: u16,
+) -> Result<Option<OAuthBlueskySession>> {
+    let session_store = CredentialSessionStore::new(credential);
+    let Some(did) = session_store.did()? else {
+        return Ok(None);
+    };
+
+    let client = create_client(session_store, plc_directory_url, callback_port)?;
+    let session = client
+        .restore(&did)
+        .await
+        .map_err(|e| eyre!("Failed to restore Bluesky OAuth session: {e}"))?;
+    info!(did = did.as_str(), "Restored Bluesky OAuth session");
+
+    Ok(Some(session))
+}
+
+/// Authorize the application in the user's browser with [atproto OAuth][oauth] as a native app, the authorization
+/// code is received on a loopback listener. PAR, PKCE and DPoP bound tokens are handled by `atrium-oauth`.
+///
+/// The flow starts from the account's PDS, unless an authorization server is given (e.g. a self-hosted entryway or a
+/// local server for testing). The session is saved to the credential store, only if it's for the account of
+/// `username`.
+///
+/// [oauth]: https://atproto.com/specs/oauth
+pub async fn login(
+    credential: Box<Credential>,
+    username: &str,
+    authorization_server: Option<&str>,
+    resolver: &IdentityResolver,
+    plc_directory_url: &str,
+    callback_port: u16,
+) -> Result<OAuthBlueskySession> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, callback_port))
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to listen on port {callback_port} for the OAuth callback, set `bluesky_oauth_callback_port` to use another port"
+            )
+        })?;
+
+    let (did, _) = resolver.resolve_pds(username).await?;
+    let input = authorization_server.map_or_else(|| did.as_str().to_string(), str::to_string);
+
+    let client = create_client(
+        CredentialSessionStore::for_account(credential, did),
+        plc_directory_url,
+        callback_port,
+    )?;
+    let authorize_url = client
+        .authorize(
+            &input,
+            AuthorizeOptions {
+                scopes: scopes(),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| eyre!("Failed to start Bluesky authorization: {e}"))?;
+
+    println!(
+        "\nPlease open this URL in your browser to authorize Bridgy Followers:\n{authorize_url}\n"
+    );
+
+    let mut query = tokio::time::timeout(
+        CALLBACK_TIMEOUT,
+        loopback::wait_for_redirect(&listener, CALLBACK_PATH, "Bluesky"),
+    )
+    .await
+    .map_err(|_| eyre!("Timed out waiting for the Bluesky authorization"))??;
+    let Some(code) = query.remove("code") else {
+        bail!("Bluesky authorization callback is missing the code");
+    };
+    let params = CallbackParams {
+        code,
+        state: query.remove("state"),
+        iss: query.remove("iss"),
+    };
+
+    let (session, _) = client
+        .callback(params)
+        .await
+        .map_err(|e| eyre!("Failed to complete Bluesky authorization: {e}"))?;
+
+    println!("✓ Authorization successful!");
+
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Request, Response};
+    use atrium_api::agent::SessionManager;
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use keyring::credential::CredentialApi;
+    use serde_json::{Value, json};
+    use sha2::{Digest, Sha256};
+    use std::{
+        any::Any,
+        sync::{Mutex, OnceLock},
+    };
+    use url::Url;
+
+    const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+    const OTHER_DID: &str = "did:plc:z72i7hdynmk6r22z27h6tvur";
+    const CODE: &str = "authorization-code";
+    const DPOP_NONCE: &str = "server-nonce";
+
+    fn session_store() -> CredentialSessionStore {
+        let credential = keyring::mock::default_credential_builder()
+            .build(None, "bridgy_followers", "oauth_session")
+            .unwrap();
+        CredentialSessionStore::new(credential)
+    }
+
+    fn session() -> Session {
+        serde_json::from_value(serde_json::json!({
+            "dpop_key": {
+                "kty": "EC",
+                "crv": "P-256",
+                "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+                "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+                "d": "jpsQnnGQmL-YBIffH1136cspYG6-0iY7X1fCE9-E9LI"
+            },
+            "token_set": {
+                "iss": "https://bsky.social",
+                "sub": DID,
+                "aud": "https://pds.example.com",
+                "scope": "atproto transition:generic",
+                "refresh_token": "refresh",
+                "access_token": "access",
+                "token_type": "DPoP",
+                "expires_at": "2026-01-01T00:00:00.000Z"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn session_store_round_trip() {
+        let store = session_store();
+        let did = Did::new(DID.to_string()).unwrap();
+        assert!(store.did().unwrap().is_none());
+
+        store.set(did.clone(), session()).await.unwrap();
+        assert_eq!(store.did().unwrap(), Some(did.clone()));
+        let stored = store.get(&did).await.unwrap().unwrap();
+        assert_eq!(stored.token_set.sub, DID);
+        assert_eq!(stored.token_set.access_token, "access");
+        assert_eq!(stored.token_set.refresh_token.as_deref(), Some("refresh"));
+
+        // Only the session of the stored account is returned
+        let other = Did::new(OTHER_DID.to_string()).unwrap();
+        assert!(store.get(&other).await.unwrap().is_none());
+
+        store.del(&did).await.unwrap();
+        assert!(store.get(&did).await.unwrap().is_none());
+        // Deleting again is not an error
+        store.clear().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn store_refuses_session_of_another_account() {
+        let credential = keyring::mock::default_credential_builder()
+            .build(None, "bridgy_followers", "oauth_session")
+            .unwrap();
+        let store = CredentialSessionStore::for_account(
+            credential,
+            Did::new(OTHER_DID.to_string()).unwrap(),
+        );
+
+        assert!(
+            store
+                .set(Did::new(DID.to_string()).unwrap(), session())
+                .await
+                .is_err()
+        );
+        assert!(store.did().unwrap().is_none());
+    }
+
+    /// A credential whose clones share the secret, like the entries of a keyring
+    #[derive(Debug, Clone, Default)]
+    struct SharedCredential(Arc<Mutex<Option<String>>>);
+
+    impl SharedCredential {
+        fn credential(&self) -> Box<Credential> {
+            Box::new(self.clone())
+        }
+
+        fn session(&self) -> Option<Session> {
+            let json = self.0.lock().unwrap().clone()?;
+            Some(serde_json::from_str(&json).unwrap())
+        }
+    }
+
+    impl CredentialApi for SharedCredential {
+        fn set_password(&self, password: &str) -> keyring::Result<()> {
+            *self.0.lock().unwrap() = Some(password.to_string());
+            Ok(())
+        }
+
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            self.set_password(&String::from_utf8_lossy(secret))
+        }
+
+        fn get_password(&self) -> keyring::Result<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            self.get_password().map(String::into_bytes)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .take()
+                .map(drop)
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    /// What the authorization server saw of the flow
+    #[derive(Debug, Default)]
+    struct Flow {
+        state: Option<String>,
+        code_challenge: Option<String>,
+        grants: Vec<String>,
+    }
+
+    /// A PLC directory, PDS and authorization server in one, issuing the tokens of `sub`
+    struct AuthorizationServer {
+        url: String,
+        flow: Arc<Mutex<Flow>>,
+    }
+
+    impl AuthorizationServer {
+        async fn start(sub: &'static str) -> Self {
+            let base = Arc::new(OnceLock::<String>::new());
+            let flow = Arc::new(Mutex::new(Flow::default()));
+            let (server_base, server_flow) = (Arc::clone(&base), Arc::clone(&flow));
+            let url = test_server::start(move |request| {
+                Some(respond(
+                    server_base.get().unwrap(),
+                    sub,
+                    &server_flow,
+                    &request,
+                ))
+            })
+            .await;
+            base.set(url.clone()).unwrap();
+            Self { url, flow }
+        }
+
+        /// Wait for the pushed authorization request and return its state
+        async fn wait_for_state(&self) -> String {
+            for _ in 0..500 {
+                if let Some(state) = self.flow.lock().unwrap().state.clone() {
+                    return state;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("No pushed authorization request");
+        }
+
+        fn grants(&self) -> Vec<String> {
+            self.flow.lock().unwrap().grants.clone()
+        }
+    }
+
+    fn oauth_error(error: &str) -> Response {
+        Response::json(400, &json!({ "error": error }))
+    }
+
+    fn respond(base: &str, sub: &str, flow: &Mutex<Flow>, request: &Request) -> Response {
+        let path = request.path();
+        if let Some(did) = path
+            .strip_prefix('/')
+            .filter(|did| did.starts_with("did:plc:"))
+        {
+            return Response::json(
+                200,
+                &json!({
+                    "id": did,
+                    "alsoKnownAs": ["at://alice.test"],
+                    "service": [{
+                        "id": "#atproto_pds",
+                        "type": "AtprotoPersonalDataServer",
+                        "serviceEndpoint": base,
+                    }],
+                }),
+            );
+        }
+
+        match path {
+            "/.well-known/oauth-protected-resource" => Response::json(
+                200,
+                &json!({ "resource": base, "authorization_servers": [base] }),
+            ),
+            "/.well-known/oauth-authorization-server" => {
+                Response::json(200, &authorization_server_metadata(base))
+            }
+            "/oauth/par" => {
+                if let Err(response) = check_dpop(base, request) {
+                    return response;
+                }
+                let form = request.form();
+                if form.get("code_challenge_method").map(String::as_str) != Some("S256") {
+                    return oauth_error("invalid_request");
+                }
+                let mut flow = flow.lock().unwrap();
+                flow.code_challenge = form.get("code_challenge").cloned();
+                flow.state = form.get("state").cloned();
+                Response::json(
+                    201,
+                    &json!({
+                        "request_uri": "urn:ietf:params:oauth:request_uri:test",
+                        "expires_in": 60,
+                    }),
+                )
+                .header("DPoP-Nonce", DPOP_NONCE)
+            }
+            "/oauth/token" => {
+                if let Err(response) = check_dpop(base, request) {
+                    return response;
+                }
+                let form = request.form();
+                let mut flow = flow.lock().unwrap();
+                let generation = match form.get("grant_type").map(String::as_str) {
+                    Some("authorization_code") => {
+                        let challenge = form
+                            .get("code_verifier")
+                            .map(|verifier| URL_SAFE_NO_PAD.encode(Sha256::digest(verifier)));
+                        if form.get("code").map(String::as_str) != Some(CODE)
+                            || challenge.is_none()
+                            || challenge != flow.code_challenge
+                        {
+                            return oauth_error("invalid_grant");
+                        }
+                        1
+                    }
+                    Some("refresh_token")
+                        if form.get("refresh_token").map(String::as_str) == Some("refresh-1") =>
+                    {
+                        2
+                    }
+                    _ => return oauth_error("invalid_grant"),
+                };
+                flow.grants.push(form["grant_type"].clone());
+                // Already stale, so that restoring the session refreshes it
+                Response::json(
+                    200,
+                    &json!({
+                        "access_token": format!("access-{generation}"),
+                        "token_type": "DPoP",
+                        "refresh_token": format!("refresh-{generation}"),
+                        "scope": "atproto transition:generic",
+                        "sub": sub,
+                        "expires_in": 1,
+                    }),
+                )
+                .header("DPoP-Nonce", DPOP_NONCE)
+            }
+            _ => Response::new(404),
+        }
+    }
+
+    fn authorization_server_metadata(base: &str) -> Value {
+        json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{base}/oauth/authorize"),
+            "token_endpoint": format!("{base}/oauth/token"),
+            "pushed_authorization_request_endpoint": format!("{base}/oauth/par"),
+            "require_pushed_authorization_requests": true,
+            "response_types_supported": ["code"],
+            "grant_types_supported": ["authorization_code", "refresh_token"],
+            "code_challenge_methods_supported": ["S256"],
+            "token_endpoint_auth_methods_supported": ["none", "private_key_jwt"],
+            "token_endpoint_auth_signing_alg_values_supported": ["ES256"],
+            "dpop_signing_alg_values_supported": ["ES256"],
+            "scopes_supported": ["atproto", "transition:generic"],
+            "authorization_response_iss_parameter_supported": true,
+            "client_id_metadata_document_supported": true,
+        })
+    }
+
+    /// Check the DPoP proof of a request, asking for a nonce first like the Bluesky authorization servers do
+    fn check_dpop(base: &str, request: &Request) -> core::result::Result<(), Response> {
+        let decode = |part: &str| -> Option<Value> {
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).ok()?).ok()
+        };
+        let mut parts = request.header("dpop").unwrap_or_default().split('.');
+        let (Some(header), Some(claims)) =
+            (parts.next().and_then(decode), parts.next().and_then(decode))
+        else {
+            return Err(oauth_error("invalid_dpop_proof"));
+        };
+
+        if header["typ"] != "dpop+jwt"
+            || header.get("jwk").is_none()
+            || claims["htm"] != request.method
+            || claims["htu"] != format!("{base}{}", request.path())
+        {
+            return Err(oauth_error("invalid_dpop_proof"));
+        }
+        if claims["nonce"] != DPOP_NONCE {
+            return Err(oauth_error("use_dpop_nonce").header("DPoP-Nonce", DPOP_NONCE));
+        }
+        Ok(())
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Do what the browser does once the user authorized the application: follow the redirect to the callback
+    async fn authorize_in_browser(server: &AuthorizationServer, callback_port: u16) {
+        let state = server.wait_for_state().await;
+        let mut url = Url::parse(&redirect_uri(callback_port)).unwrap();
+        url.query_pairs_mut()
+            .append_pair("code", CODE)
+            .append_pair("state", &state)
+            .append_pair("iss", &server.url);
+        reqwest::get(url).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_and_refresh() {
+        let server = AuthorizationServer::start(DID).await;
+        let resolver = IdentityResolver::new(&server.url, None).unwrap();
+        let callback_port = free_port();
+        let stored = SharedCredential::default();
+
+        let (session, ()) = tokio::join!(
+            login(
+                stored.credential(),
+                DID,
+                None,
+                &resolver,
+                &server.url,
+                callback_port
+            ),
+            authorize_in_browser(&server, callback_port),
+        );
+        let session = session.unwrap();
+        assert_eq!(session.did().await.unwrap().as_str(), DID);
+        assert_eq!(stored.session().unwrap().token_set.access_token, "access-1");
+
+        let session = restore(stored.credential(), &server.url, callback_port)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.did().await.unwrap().as_str(), DID);
+        assert_eq!(server.grants(), ["authorization_code", "refresh_token"]);
+        let refreshed = stored.session().unwrap().token_set;
+        assert_eq!(refreshed.access_token, "access-2");
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("refresh-2"));
+    }
+
+    #[tokio::test]
+    async fn login_rejects_session_of_another_account() {
+        let server = AuthorizationServer::start(OTHER_DID).await;
+        let resolver = IdentityResolver::new(&server.url, None).unwrap();
+        let callback_port = free_port();
+        let stored = SharedCredential::default();
+
+        let (session, ()) = tokio::join!(
+            login(
+                stored.credential(),
+                DID,
+                None,
+                &resolver,
+                &server.url,
+                callback_port
+            ),
+            authorize_in_browser(&server, callback_port),
+        );
+
+        // The authorization server answered with the tokens of another account than the configured one
+        assert!(session.is_err());
+        assert!(stored.session().is_none());
+    }
+}
@@ -0,0 +1,106 @@
+use super::oauth::OAuthBlueskySession;
+use atrium_api::{
+    agent::{
+        CloneWithProxy, SessionManager,
+        atp_agent::{CredentialSession, store::MemorySessionStore},
+    },
+    types::string::Did,
+    xrpc::{
+        Error, HttpClient, OutputDataOrBytes, XrpcClient, XrpcRequest,
+        http::{Request, Response},
+        types::AuthorizationToken,
+    },
+};
+use atrium_xrpc_client::reqwest::ReqwestClient;
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt::Debug;
+
+/// A Bluesky session, either logged in with an app password or authorized with OAuth.
+///
+/// Requests are forwarded to the underlying session so that OAuth requests keep their DPoP proofs. Proxied clones share
+/// the tokens of the session they were cloned from.
+pub enum BlueskySession {
+    AppPassword(CredentialSession<MemorySessionStore, ReqwestClient>),
+    OAuth(OAuthBlueskySession),
+}
+
+impl HttpClient for BlueskySession {
+    async fn send_http(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> core::result::Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        match self {
+            BlueskySession::AppPassword(session) => session.send_http(request).await,
+            BlueskySession::OAuth(session) => session.send_http(request).await,
+        }
+    }
+}
+
+impl XrpcClient for BlueskySession {
+    fn base_uri(&self) -> String {
+        match self {
+            BlueskySession::AppPassword(session) => session.base_uri(),
+            BlueskySession::OAuth(session) => session.base_uri(),
+        }
+    }
+
+    async fn authorization_token(&self, is_refresh: bool) -> Option<AuthorizationToken> {
+        match self {
+            BlueskySession::AppPassword(session) => session.authorization_token(is_refresh).await,
+            BlueskySession::OAuth(session) => session.authorization_token(is_refresh).await,
+        }
+    }
+
+    async fn atproto_proxy_header(&self) -> Option<String> {
+        match self {
+            BlueskySession::AppPassword(session) => session.atproto_proxy_header().await,
+            BlueskySession::OAuth(session) => session.atproto_proxy_header().await,
+        }
+    }
+
+    async fn atproto_accept_labelers_header(&self) -> Option<Vec<String>> {
+        match self {
+            BlueskySession::AppPassword(session) => session.atproto_accept_labelers_header().await,
+            BlueskySession::OAuth(session) => session.atproto_accept_labelers_header().await,
+        }
+    }
+
+    async fn send_xrpc<P, I, O, E>(
+        &self,
+        request: &XrpcRequest<P, I>,
+    ) -> core::result::Result<OutputDataOrBytes<O>, Error<E>>
+    where
+        P: Serialize + Send + Sync,
+        I: Serialize + Send + Sync,
+        O: DeserializeOwned + Send + Sync,
+        E: DeserializeOwned + Send + Sync + Debug,
+    {
+        match self {
+            BlueskySession::AppPassword(session) => session.send_xrpc(request).await,
+            BlueskySession::OAuth(session) => session.send_xrpc(request).await,
+        }
+    }
+}
+
+impl CloneWithProxy for BlueskySession {
+    fn clone_with_proxy(&self, did: Did, service_type: impl AsRef<str>) -> Self {
+        match self {
+            BlueskySession::AppPassword(session) => {
+                BlueskySession::AppPassword(session.clone_with_proxy(did, service_type))
+            }
+            BlueskySession::OAuth(session) => {
+                BlueskySession::OAuth(session.clone_with_proxy(did, service_type))
+            }
+        }
+    }
+}
+
+impl SessionManager for BlueskySession {
+    async fn did(&self) -> Option<Did> {
+        match self {
+            BlueskySession::AppPassword(session) => session.did().await,
+            BlueskySession::OAuth(session) => session.did().await,
+        }
+    }
+}
@@ -1,17 +1,26 @@
-use super::utils::{BlueskyAgent, create_agent};
+use super::{
+    identity::{IdentityResolver, ServiceRef},
+    oauth::{self, OAuthBlueskySession},
+    session::BlueskySession,
+    utils::{BlueskyAgent, create_agent, resolve_handle},
+};
 use crate::{
-    config::{Config, ConfigData},
-    credentials,
+    config::{BlueskyAuth, CONFIG_VERSION, Config, ConfigData},
+    credentials, interactive,
 };
+use atrium_api::types::string::Did;
 use color_eyre::{Result, eyre::Context};
 use dialoguer::{Input, Password, theme::ColorfulTheme};
 use keyring::CredentialBuilder;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
 
 /// Get the Bluesky username from config or prompt if not set
 fn get_username(config: &mut Config) -> Result<String> {
     if let Some(server) = config.bluesky_username() {
         Ok(server.to_string())
     } else {
+        interactive::require_setting("bluesky_username")?;
         let input: String = Input::with_theme(&ColorfulTheme::default())
             .with_prompt("Bluesky username (e.g., user.bsky.social)")
             .interact_text()?;
@@ -25,13 +34,17 @@ fn get_username(config: &mut Config) -> Result<String> {
 }
 
 /// Get the Bluesky password from the credential store or prompt if not set
-fn get_password(credential_builder: &Box<CredentialBuilder>, username: &str) -> Result<String> {
-    // TODO: We should use OAuth now that it's available in bluesky
-    let credentials = credentials::get_bluesky_password(credential_builder, username)?;
+fn get_password(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    username: &str,
+) -> Result<String> {
+    let credentials = credentials::get_bluesky_password(credential_builder, profile, username)?;
 
     match credentials.get_password() {
         Ok(password) => Ok(password),
         Err(keyring::Error::NoEntry) => {
+            interactive::require_credential(&format!("Bluesky app password for {username}"))?;
             let password = Password::with_theme(&ColorfulTheme::default())
                 .with_prompt("Bluesky Password")
                 .interact()?;
@@ -47,7 +60,164 @@ pub async fn authenticate(
     config: &mut Config,
 ) -> Result<BlueskyAgent> {
     let username = get_username(config)?;
-    let password = get_password(credential_builder, &username)?;
+    let appview = config
+        .bluesky_appview()
+        .map(ServiceRef::parse)
+        .transpose()?;
+    let resolver = IdentityResolver::new(config.plc_directory_url(), appview.clone())?;
+    let login = Login {
+        credential_builder,
+        config,
+        username: &username,
+        resolver: &resolver,
+        appview: appview.as_ref(),
+    };
+
+    let agent = match config.bluesky_auth()? {
+        BlueskyAuth::AppPassword => login.app_password().await?,
+        BlueskyAuth::OAuth => {
+            let has_app_password =
+                credentials::get_bluesky_password(credential_builder, config.profile(), &username)?
+                    .get_password()
+                    .is_ok();
+            match login.oauth(has_app_password).await {
+                Ok(Some(session)) => {
+                    BlueskyAgent::new(BlueskySession::OAuth(session), appview.as_ref())
+                }
+                // Logged in with an app password before OAuth was supported, keep using it until `forget`
+                Ok(None) => login.app_password().await?,
+                Err(e) if !interactive::is_interactive() => return Err(e),
+                Err(e) => {
+                    warn!("Bluesky OAuth login failed, falling back to an app password: {e}");
+                    login.app_password().await?
+                }
+            }
+        }
+    };
+    if config.version() < CONFIG_VERSION {
+        migrate_ignored_accounts(&agent, config).await?;
+    }
+
+    Ok(agent)
+}
+
+/// Resolve a handle to its DID without logging in, for commands that don't need a Bluesky session
+pub async fn resolve_handle_without_login(config: &Config, handle: &str) -> Result<Did> {
+    let appview = config
+        .bluesky_appview()
+        .map(ServiceRef::parse)
+        .transpose()?;
+    IdentityResolver::new(config.plc_directory_url(), appview)?
+        .resolve_handle(handle)
+        .await
+}
+
+/// What's needed to log in to Bluesky, with either method
+struct Login<'a> {
+    credential_builder: &'a Box<CredentialBuilder>,
+    config: &'a Config,
+    username: &'a str,
+    resolver: &'a IdentityResolver,
+    appview: Option<&'a ServiceRef>,
+}
+
+impl Login<'_> {
+    /// Log in with an app password on the PDS found in the DID document of the account
+    async fn app_password(&self) -> Result<BlueskyAgent> {
+        let password = get_password(
+            self.credential_builder,
+            self.config.profile(),
+            self.username,
+        )?;
+        let (_, pds) = self.resolver.resolve_pds(self.username).await?;
+        create_agent(&pds, self.username, &password, self.appview).await
+    }
+
+    /// Restore the stored OAuth session, or authorize in the browser if there is none. Returns `None` without
+    /// starting the flow when an app password is stored instead.
+    async fn oauth(&self, has_app_password: bool) -> Result<Option<OAuthBlueskySession>> {
+        let profile = self.config.profile();
+        let plc_directory_url = self.config.plc_directory_url();
+
+        let credential = credentials::get_bluesky_oauth_session(
+            self.credential_builder,
+            profile,
+            self.username,
+        )?;
+        let callback_port = self.config.bluesky_oauth_callback_port();
+        match oauth::restore(credential, plc_directory_url, callback_port).await {
+            Ok(Some(session)) => return Ok(Some(session)),
+            Ok(None) if has_app_password => return Ok(None),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Stored Bluesky OAuth session can't be used anymore, authorizing again: {e}")
+            }
+        }
+
+        let credential = credentials::get_bluesky_oauth_session(
+            self.credential_builder,
+            profile,
+            self.username,
+        )?;
+        interactive::require_credential(&format!("Bluesky OAuth session for {}", self.username))?;
+        let session = oauth::login(
+            credential,
+            self.username,
+            self.config.bluesky_oauth_server(),
+            self.resolver,
+            plc_directory_url,
+            callback_port,
+        )
+        .await?;
+        Ok(Some(session))
+    }
+}
 
-    create_agent(&username, &password).await
+/// Move the ignores stored by handle to the ignores stored by DID, so that they keep working when the account changes
+/// handle. Handles that can't be resolved are kept as they are. Done once per profile, as ignores are stored by DID
+/// from then on.
+async fn migrate_ignored_accounts(agent: &BlueskyAgent, config: &mut Config) -> Result<()> {
+    let mut resolved = BTreeMap::new();
+    for ignored in config.ignored_accounts() {
+        let handle = ignored.trim_start_matches('@');
+        // Mastodon addresses, ignored when syncing from Mastodon to Bluesky, aren't Bluesky handles
+        if handle.contains('@') {
+            continue;
+        }
+
+        match resolve_handle(agent, handle).await {
+            Ok(did) => {
+                info!(did = did.as_str(), "Ignored account '{handle}' resolved");
+                resolved.insert(ignored.clone(), (did, handle.to_string()));
+            }
+            Err(e) => {
+                warn!("Failed to resolve ignored account '{handle}', keeping it by handle: {e}")
+            }
+        }
+    }
+
+    config.mutate(|mut data| {
+        data.version = CONFIG_VERSION;
+        data.ignored_accounts
+            .retain(|ignored| !resolved.contains_key(ignored));
+        for (did, handle) in resolved.values() {
+            data.ignored_dids
+                .insert(did.as_str().to_string(), handle.clone());
+        }
+        data
+    })
+}
+
+/// Check that the session of an agent is still valid, logging in again if it can't be used anymore (e.g. the
+/// refresh token expired in a long running process). The agent is left as is when logging in again fails.
+pub async fn ensure_session(
+    agent: &mut BlueskyAgent,
+    credential_builder: &Box<CredentialBuilder>,
+    config: &mut Config,
+) -> Result<()> {
+    if let Err(e) = agent.api.com.atproto.server.get_session().await {
+        warn!("Bluesky session can't be used anymore, logging in again: {e}");
+        *agent = authenticate(credential_builder, config).await?;
+    }
+    Ok(())
 }
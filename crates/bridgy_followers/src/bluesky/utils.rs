@@ -1,35 +1,98 @@
+use super::{identity::ServiceRef, session::BlueskySession};
+use crate::exit_code::AuthRequired;
 use atrium_api::{
-    agent::atp_agent::{AtpAgent, store::MemorySessionStore},
+    agent::{
+        Agent, CloneWithProxy,
+        atp_agent::{CredentialSession, store::MemorySessionStore},
+    },
     app::bsky::{
-        actor::defs::ProfileViewData,
+        actor::defs::{ProfileViewData, ProfileViewDetailedData},
         graph::{
             defs::Relationship,
             get_relationships::{self, OutputRelationshipsItem},
         },
     },
     types::{
-        Object, Union,
-        string::{AtIdentifier, Did},
+        Object, TryIntoUnknown, Union,
+        string::{AtIdentifier, Datetime, Did, Nsid},
     },
+    xrpc,
 };
 use atrium_xrpc_client::reqwest::ReqwestClient;
-use color_eyre::{Result, eyre::eyre};
-use std::collections::HashMap;
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail, eyre},
+};
+use reqwest::StatusCode;
+use std::{collections::HashMap, ops::Deref};
 use tracing::instrument;
+use url::Url;
+
+/// A Bluesky agent, with AppView queries optionally proxied by the PDS to a configured AppView
+pub struct BlueskyAgent {
+    agent: Agent<BlueskySession>,
+    appview: Option<Agent<BlueskySession>>,
+}
+
+impl BlueskyAgent {
+    pub fn new(session: BlueskySession, appview: Option<&ServiceRef>) -> Self {
+        let appview = appview.map(|appview| {
+            Agent::new(session.clone_with_proxy(appview.did.clone(), &appview.service_id))
+        });
+        Self {
+            agent: Agent::new(session),
+            appview,
+        }
+    }
 
-pub type BlueskyAgent = AtpAgent<MemorySessionStore, ReqwestClient>;
+    /// The agent to use for `app.bsky` queries (e.g. `getKnownFollowers` or `getRelationships`). Other requests, such as
+    /// creating records, must go to the PDS itself.
+    pub fn appview(&self) -> &Agent<BlueskySession> {
+        self.appview.as_ref().unwrap_or(&self.agent)
+    }
+}
+
+impl Deref for BlueskyAgent {
+    type Target = Agent<BlueskySession>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.agent
+    }
+}
 
 const BRIDGY_HANDLE: &str = "ap.brid.gy";
 
-pub async fn create_agent(username: &str, password: &str) -> Result<BlueskyAgent> {
-    let agent = AtpAgent::new(
-        ReqwestClient::new("https://bsky.social"),
+const LIST_COLLECTION: &str = "app.bsky.graph.list";
+const STARTER_PACK_COLLECTION: &str = "app.bsky.graph.starterpack";
+
+/// Log in with an app password on the PDS hosting the account
+pub async fn create_agent(
+    pds: &Url,
+    username: &str,
+    password: &str,
+    appview: Option<&ServiceRef>,
+) -> Result<BlueskyAgent> {
+    let session = CredentialSession::new(
+        ReqwestClient::new(pds.as_str().trim_end_matches('/')),
         MemorySessionStore::default(),
     );
 
-    agent.login(username, password).await?;
+    if let Err(e) = session.login(username, password).await {
+        // The app password was revoked or mistyped, only the user can provide a new one
+        if let xrpc::Error::XrpcResponse(response) = &e
+            && response.status == StatusCode::UNAUTHORIZED
+        {
+            return Err(e).wrap_err(AuthRequired(format!(
+                "The Bluesky app password for {username} was rejected by {pds}, run `bridgy_followers forget` and log in again"
+            )));
+        }
+        return Err(e).wrap_err_with(|| format!("Failed to log in to {pds}"));
+    }
 
-    Ok(agent)
+    Ok(BlueskyAgent::new(
+        BlueskySession::AppPassword(session),
+        appview,
+    ))
 }
 
 /// Resolve a Bluesky handle or DID to a DID
@@ -86,6 +149,7 @@ pub async fn get_known_followers(
         };
 
         let response = agent
+            .appview()
             .api
             .app
             .bsky
@@ -106,6 +170,349 @@ pub async fn get_known_followers(
     Ok(all_followers)
 }
 
+/// Get the DID of the logged in user
+pub async fn get_own_did(agent: &BlueskyAgent) -> Result<Did> {
+    agent
+        .did()
+        .await
+        .ok_or_else(|| eyre!("Not logged in to Bluesky"))
+}
+
+/// Enumerates all the accounts followed by an actor.
+#[instrument(skip(agent))]
+pub async fn get_follows(
+    agent: &BlueskyAgent,
+    actor: &Did,
+) -> Result<HashMap<Did, Object<ProfileViewData>>> {
+    use atrium_api::app::bsky::graph::get_follows;
+
+    let mut all_follows = HashMap::new();
+    let mut cursor = None;
+
+    loop {
+        let params = get_follows::ParametersData {
+            actor: actor.clone().into(),
+            cursor: cursor.clone(),
+            limit: Some(
+                100.try_into()
+                    .map_err(|e| eyre!("Failed to convert limit: {}", e))?,
+            ),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .graph
+            .get_follows(params.into())
+            .await?;
+
+        for follow in response.data.follows {
+            all_follows.insert(follow.did.clone(), follow);
+        }
+
+        if response.data.cursor.is_none() {
+            break;
+        }
+        cursor = response.data.cursor;
+    }
+
+    Ok(all_follows)
+}
+
+/// Enumerates all the accounts following an actor.
+#[instrument(skip(agent))]
+pub async fn get_followers(
+    agent: &BlueskyAgent,
+    actor: &Did,
+) -> Result<HashMap<Did, Object<ProfileViewData>>> {
+    use atrium_api::app::bsky::graph::get_followers;
+
+    let mut all_followers = HashMap::new();
+    let mut cursor = None;
+
+    loop {
+        let params = get_followers::ParametersData {
+            actor: actor.clone().into(),
+            cursor: cursor.clone(),
+            limit: Some(
+                100.try_into()
+                    .map_err(|e| eyre!("Failed to convert limit: {}", e))?,
+            ),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .graph
+            .get_followers(params.into())
+            .await?;
+
+        for follower in response.data.followers {
+            all_followers.insert(follower.did.clone(), follower);
+        }
+
+        if response.data.cursor.is_none() {
+            break;
+        }
+        cursor = response.data.cursor;
+    }
+
+    Ok(all_followers)
+}
+
+/// Enumerates all the accounts blocked by the logged in user.
+#[instrument(skip(agent))]
+pub async fn get_blocks(agent: &BlueskyAgent) -> Result<HashMap<Did, Object<ProfileViewData>>> {
+    use atrium_api::app::bsky::graph::get_blocks;
+
+    let mut all_blocks = HashMap::new();
+    let mut cursor = None;
+
+    loop {
+        let params = get_blocks::ParametersData {
+            cursor: cursor.clone(),
+            limit: Some(
+                100.try_into()
+                    .map_err(|e| eyre!("Failed to convert limit: {}", e))?,
+            ),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .graph
+            .get_blocks(params.into())
+            .await?;
+
+        for block in response.data.blocks {
+            all_blocks.insert(block.did.clone(), block);
+        }
+
+        if response.data.cursor.is_none() {
+            break;
+        }
+        cursor = response.data.cursor;
+    }
+
+    Ok(all_blocks)
+}
+
+/// Enumerates all the accounts muted by the logged in user.
+#[instrument(skip(agent))]
+pub async fn get_mutes(agent: &BlueskyAgent) -> Result<HashMap<Did, Object<ProfileViewData>>> {
+    use atrium_api::app::bsky::graph::get_mutes;
+
+    let mut all_mutes = HashMap::new();
+    let mut cursor = None;
+
+    loop {
+        let params = get_mutes::ParametersData {
+            cursor: cursor.clone(),
+            limit: Some(
+                100.try_into()
+                    .map_err(|e| eyre!("Failed to convert limit: {}", e))?,
+            ),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .graph
+            .get_mutes(params.into())
+            .await?;
+
+        for mute in response.data.mutes {
+            all_mutes.insert(mute.did.clone(), mute);
+        }
+
+        if response.data.cursor.is_none() {
+            break;
+        }
+        cursor = response.data.cursor;
+    }
+
+    Ok(all_mutes)
+}
+
+/// Convert a list or starter pack reference, either an AT-URI or a bsky.app URL, to an AT-URI with a DID authority
+async fn resolve_record_uri(agent: &BlueskyAgent, uri: &str, collection: &str) -> Result<String> {
+    let (actor, rkey) = if let Some(path) = uri.strip_prefix("at://") {
+        match path.split('/').collect::<Vec<_>>().as_slice() {
+            [actor, record_collection, rkey] if *record_collection == collection => {
+                (actor.to_string(), rkey.to_string())
+            }
+            _ => bail!("'{uri}' is not the AT-URI of a {collection} record"),
+        }
+    } else {
+        let url = Url::parse(uri).wrap_err_with(|| format!("Failed to parse URL '{uri}'"))?;
+        let segments: Vec<_> = url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        match segments.as_slice() {
+            ["profile", actor, "lists", rkey] if collection == LIST_COLLECTION => {
+                (actor.to_string(), rkey.to_string())
+            }
+            ["starter-pack", actor, rkey] if collection == STARTER_PACK_COLLECTION => {
+                (actor.to_string(), rkey.to_string())
+            }
+            _ => bail!("'{uri}' is not a Bluesky URL of a {collection} record"),
+        }
+    };
+
+    let did = resolve_handle(agent, &actor).await?;
+    Ok(format!("at://{}/{collection}/{rkey}", did.as_str()))
+}
+
+/// Enumerates all the members of a list, given as an AT-URI or a bsky.app URL.
+#[instrument(skip(agent))]
+pub async fn get_list_members(
+    agent: &BlueskyAgent,
+    list: &str,
+) -> Result<HashMap<Did, Object<ProfileViewData>>> {
+    use atrium_api::app::bsky::graph::get_list;
+
+    let list = resolve_record_uri(agent, list, LIST_COLLECTION).await?;
+
+    let mut all_members = HashMap::new();
+    let mut cursor = None;
+
+    loop {
+        let params = get_list::ParametersData {
+            cursor: cursor.clone(),
+            limit: Some(
+                100.try_into()
+                    .map_err(|e| eyre!("Failed to convert limit: {}", e))?,
+            ),
+            list: list.clone(),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .graph
+            .get_list(params.into())
+            .await
+            .wrap_err_with(|| format!("Failed to get list {list}"))?;
+
+        for item in response.data.items {
+            let member = item.data.subject;
+            all_members.insert(member.did.clone(), member);
+        }
+
+        if response.data.cursor.is_none() {
+            break;
+        }
+        cursor = response.data.cursor;
+    }
+
+    Ok(all_members)
+}
+
+/// Get the AT-URI of the list of accounts referenced by a starter pack, given as an AT-URI or a bsky.app URL.
+#[instrument(skip(agent))]
+pub async fn get_starter_pack_list(agent: &BlueskyAgent, starter_pack: &str) -> Result<String> {
+    use atrium_api::app::bsky::graph::get_starter_pack;
+
+    let starter_pack = resolve_record_uri(agent, starter_pack, STARTER_PACK_COLLECTION).await?;
+    let params = get_starter_pack::ParametersData {
+        starter_pack: starter_pack.clone(),
+    };
+
+    let response = agent
+        .appview()
+        .api
+        .app
+        .bsky
+        .graph
+        .get_starter_pack(params.into())
+        .await
+        .wrap_err_with(|| format!("Failed to get starter pack {starter_pack}"))?;
+
+    response
+        .data
+        .starter_pack
+        .data
+        .list
+        .map(|list| list.data.uri)
+        .ok_or_else(|| eyre!("Starter pack {starter_pack} has no list of accounts"))
+}
+
+/// Get the profiles of multiple actors, actors that don't exist are omitted from the result
+#[instrument(skip(agent, actors))]
+pub async fn get_profiles(
+    agent: &BlueskyAgent,
+    actors: impl IntoIterator<Item = AtIdentifier>,
+) -> Result<Vec<Object<ProfileViewDetailedData>>> {
+    use atrium_api::app::bsky::actor::get_profiles;
+
+    // The API has a limit on how many actors can be queried at once (25), so we chunk them
+    let all_actors = actors.into_iter().collect::<Vec<_>>();
+    let chunks = all_actors.chunks(25);
+
+    let mut result = Vec::new();
+    for chunk in chunks {
+        let params = get_profiles::ParametersData {
+            actors: chunk.to_vec(),
+        };
+
+        let response = agent
+            .appview()
+            .api
+            .app
+            .bsky
+            .actor
+            .get_profiles(params.into())
+            .await?;
+        result.extend(response.data.profiles);
+    }
+
+    Ok(result)
+}
+
+/// Follow an account from the logged in user's account
+#[instrument(skip(agent))]
+pub async fn follow_account(agent: &BlueskyAgent, subject: &Did) -> Result<()> {
+    use atrium_api::{app::bsky::graph::follow, com::atproto::repo::create_record};
+
+    let own_did = get_own_did(agent).await?;
+    let record: follow::Record = follow::RecordData {
+        created_at: Datetime::now(),
+        subject: subject.clone(),
+    }
+    .into();
+
+    let input = create_record::InputData {
+        collection: Nsid::new("app.bsky.graph.follow".to_string())
+            .map_err(|e| eyre!("Failed to parse NSID: {e}"))?,
+        record: record.try_into_unknown()?,
+        repo: own_did.into(),
+        rkey: None,
+        swap_commit: None,
+        validate: None,
+    };
+
+    agent
+        .api
+        .com
+        .atproto
+        .repo
+        .create_record(input.into())
+        .await?;
+
+    Ok(())
+}
+
 #[instrument(skip(bluesky, actor, others))]
 pub async fn get_relationships(
     bluesky: &BlueskyAgent,
@@ -124,6 +531,7 @@ pub async fn get_relationships(
         };
 
         let relationships = bluesky
+            .appview()
             .api
             .app
             .bsky
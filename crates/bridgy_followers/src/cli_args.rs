@@ -1,13 +1,26 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
-use crate::config;
+use crate::{
+    config,
+    follower_status::{AccountSource, FollowerSource},
+    report::ReportFormat,
+};
 
 fn default_config_path() -> PathBuf {
     config::default_config_path().unwrap_or_else(|_| PathBuf::from("bridgy_followers.toml"))
 }
 
+/// Which way accounts are synced
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SyncDirection {
+    /// Follow on Mastodon the bridged accounts of people followed on Bluesky
+    BlueskyToMastodon,
+    /// Follow on Bluesky the bridged accounts of people followed on Mastodon
+    MastodonToBluesky,
+}
+
 #[derive(Parser)]
 pub enum Command {
     /// Sync followers from Bluesky to Mastodon (follows new bridged accounts automatically)
@@ -16,10 +29,134 @@ pub enum Command {
         #[arg(default_value_os_t = default_config_path())]
         config: PathBuf,
 
+        /// Which way to sync follows
+        #[arg(long, value_enum, default_value_t = SyncDirection::BlueskyToMastodon)]
+        direction: SyncDirection,
+
+        /// Which Bluesky accounts to follow on Mastodon (overrides `follower_source` from the configuration)
+        #[arg(long, value_enum)]
+        source: Option<FollowerSource>,
+
+        /// Follow the members of a Bluesky list, given as an AT-URI or a bsky.app URL
+        #[arg(long, value_name = "LIST", conflicts_with = "source")]
+        from_list: Option<String>,
+
+        /// Follow the members of a Bluesky starter pack, given as an AT-URI or a bsky.app URL
+        #[arg(long, value_name = "STARTER_PACK", conflicts_with_all = ["source", "from_list"])]
+        from_starter_pack: Option<String>,
+
+        /// Only print the accounts that would be followed, without following them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Write the accounts that would be followed to a plan file instead of following them (see `apply`)
+        #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+        plan: Option<PathBuf>,
+
+        /// Resume an interrupted sync, following the remaining accounts without recomputing statuses
+        #[arg(long, conflicts_with_all = ["dry_run", "plan", "source", "from_list", "from_starter_pack"])]
+        resume: bool,
+
+        /// Sync every profile of the configuration, one after the other
+        #[arg(long, conflicts_with_all = ["profile", "plan", "resume"])]
+        all_profiles: bool,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Keep running and sync followers from Bluesky to Mastodon at a regular interval
+    Watch {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Minutes between two syncs (overrides `watch_interval_minutes` from the configuration)
+        #[arg(long, value_name = "MINUTES")]
+        interval: Option<u64>,
+
         /// Increase verbosity level.
         #[arg(short, long, action = clap::ArgAction::Count)]
         verbose: u8,
     },
+    /// Follow the accounts from a plan created by `sync --plan`
+    Apply {
+        /// Path to the plan file
+        plan: PathBuf,
+
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Unfollow bridged accounts on Mastodon that are no longer followed on Bluesky
+    Reconcile {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Also unfollow bridged accounts that were followed manually rather than by this tool
+        #[arg(long)]
+        include_manual: bool,
+
+        /// Unfollow without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// List the followed bridged accounts whose owners stopped bridging, and optionally unfollow them
+    Prune {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Unfollow the accounts that stopped bridging
+        #[arg(long)]
+        unfollow: bool,
+
+        /// Unfollow without asking for confirmation
+        #[arg(short, long, requires = "unfollow")]
+        yes: bool,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Replace the follows of bridged accounts made under an old Bluesky handle with their current handle
+    Repair {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Repair without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Add all the bridged accounts followed on Mastodon to the configured Mastodon list
+    BackfillList {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Mirror Bluesky moderation on Mastodon
+    Moderation {
+        #[command(subcommand)]
+        command: ModerationCommand,
+    },
     /// Generate a CSV that can be imported into mastodon UI
     Csv {
         /// Path to configuration file
@@ -30,6 +167,62 @@ pub enum Command {
         #[arg(short, long)]
         output: Option<PathBuf>,
 
+        /// Which Bluesky accounts to include (overrides `follower_source` from the configuration)
+        #[arg(long, value_enum)]
+        source: Option<FollowerSource>,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Report the status of every account, in a format that scripts can consume
+    Report {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Which Bluesky accounts to include (overrides `follower_source` from the configuration)
+        #[arg(long, value_enum)]
+        source: Option<FollowerSource>,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Explain the status of a single account by running every check on it
+    Explain {
+        /// Account handle or DID (e.g., user.bsky.social)
+        account: String,
+
+        /// Path to configuration file
+        #[arg(short, long, default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+    /// Show recorded runs, or the history of a single account
+    History {
+        /// Account handle or DID (e.g., user.bsky.social). If not provided, lists recent runs.
+        account: Option<String>,
+
+        /// Show the status of the account at this point in time (e.g., "2025-01-02" or "1 week ago")
+        #[arg(long, requires = "account")]
+        at: Option<String>,
+
+        /// Path to configuration file
+        #[arg(short, long, default_value_os_t = default_config_path())]
+        config: PathBuf,
+
         /// Increase verbosity level.
         #[arg(short, long, action = clap::ArgAction::Count)]
         verbose: u8,
@@ -49,6 +242,11 @@ pub enum Command {
         #[command(subcommand)]
         command: IgnoresCommand,
     },
+    /// Manage profiles
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommand,
+    },
     // Get the default config path
     Config {
         /// Increase verbosity level.
@@ -80,14 +278,88 @@ pub enum IgnoresCommand {
     },
 }
 
+#[derive(Parser)]
+pub enum ProfilesCommand {
+    /// Add an empty profile, its accounts are asked for the first time it's used with `--profile`
+    Add {
+        /// Name of the profile (ASCII letters, digits, `_` and `-`)
+        name: String,
+
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+}
+
+/// The accounts source selected by the `sync` arguments, if any
+pub fn account_source(
+    source: Option<FollowerSource>,
+    from_list: Option<String>,
+    from_starter_pack: Option<String>,
+) -> Option<AccountSource> {
+    from_list
+        .map(AccountSource::List)
+        .or(from_starter_pack.map(AccountSource::StarterPack))
+        .or(source.map(AccountSource::Graph))
+}
+
+#[derive(Parser)]
+pub enum ModerationCommand {
+    /// Block and mute on Mastodon the bridged accounts blocked and muted on Bluesky, lifting them once lifted on Bluesky
+    Sync {
+        /// Path to configuration file
+        #[arg(default_value_os_t = default_config_path())]
+        config: PathBuf,
+
+        /// Only print the changes that would be made, without making them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Increase verbosity level.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+    },
+}
+
 impl Command {
     pub fn verbose(&self) -> u8 {
         match self {
             Command::Sync { verbose, .. }
+            | Command::Apply { verbose, .. }
+            | Command::Watch { verbose, .. }
+            | Command::Reconcile { verbose, .. }
+            | Command::Repair { verbose, .. }
+            | Command::Prune { verbose, .. }
+            | Command::BackfillList { verbose, .. }
             | Command::Forget { verbose, .. }
             | Command::Csv { verbose, .. }
+            | Command::Report { verbose, .. }
+            | Command::Explain { verbose, .. }
+            | Command::History { verbose, .. }
             | Command::Config { verbose, .. } => *verbose,
             Command::Ignores { command } => command.verbose(),
+            Command::Profiles { command } => command.verbose(),
+            Command::Moderation { command } => command.verbose(),
+        }
+    }
+}
+
+impl ProfilesCommand {
+    pub fn verbose(&self) -> u8 {
+        match self {
+            ProfilesCommand::Add { verbose, .. } => *verbose,
+        }
+    }
+}
+
+impl ModerationCommand {
+    pub fn verbose(&self) -> u8 {
+        match self {
+            ModerationCommand::Sync { verbose, .. } => *verbose,
         }
     }
 }
@@ -106,4 +378,14 @@ impl IgnoresCommand {
 pub struct CliArgs {
     #[command(subcommand)]
     pub command: Command,
+
+    /// Profile to use, each profile has its own Bluesky and Mastodon accounts and ignore list (defaults to the
+    /// accounts at the top level of the configuration)
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Never prompt, fail with an error naming the missing setting or credential instead. Enabled automatically when
+    /// stdin isn't a terminal. Only when passed explicitly, a command with nothing to do exits with code 5
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
 }
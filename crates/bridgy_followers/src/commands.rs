@@ -1,49 +1,1556 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crate::bluesky::{self};
-use crate::config::{Config, default_config_path};
-use crate::follower_status::{FollowerStatus, get_follower_statuses, statuses_to_import_csv};
-use crate::{credentials, mastodon, utils::bluesky_handle_to_mastodon};
-use color_eyre::Result;
+use crate::bluesky::{self, BlueskyAgent};
+use crate::cli_args::SyncDirection;
+use crate::config::{Config, default_config_path, watch_delay};
+use crate::exit_code::Outcome;
+use crate::fediverse_status::get_fediverse_statuses;
+use crate::follow_executor::{FollowCheckpoint, FollowExecutor, FollowSummary, checkpoint_path};
+use crate::follower_status::{
+    AccountSource, BlueskyAccount, BridgedFollower, FollowerSource, FollowerStatus,
+    NotBridgedReason, blocks_actor, check_bridged, get_follower_statuses, statuses_to_import_csv,
+};
+use crate::plan::FollowPlan;
+use crate::report::{ReportFormat, statuses_to_report};
+use crate::state::{FollowOutcome, ModerationAction, RunId, State};
+use crate::utils::{
+    BRIDGY_ACTIVITY_PUB_URL, BRIDGY_MASTODON_DOMAIN, bluesky_handle_to_mastodon, did_from_url,
+    format_timestamp, mastodon_acct_to_bluesky_handle, parse_point_in_time,
+};
+use crate::watch::{ShutdownSignal, next_delay};
+use crate::{
+    activitypub, credentials, interactive,
+    mastodon::{self, MastodonClient},
+    webfinger,
+};
+use atrium_api::types::string::{AtIdentifier, Did, Handle};
 use color_eyre::owo_colors::OwoColorize;
+use color_eyre::{
+    Result,
+    eyre::{bail, eyre},
+};
 use dialoguer::{MultiSelect, theme::ColorfulTheme};
+use futures::{StreamExt, stream};
+use jiff::Timestamp;
+use megalodon::{entities::Account, mastodon::Mastodon};
+use tracing::{error, info, warn};
 
-pub async fn sync_command(config_path: PathBuf, _output_path: Option<PathBuf>) -> Result<()> {
-    let mut config = Config::from_file(&config_path)?;
+/// Handle reported by Bluesky for accounts whose handle doesn't resolve back to their DID
+const INVALID_HANDLE: &str = "handle.invalid";
 
-    let credential_builder = keyring::default::default_credential_builder();
+pub async fn sync_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    direction: SyncDirection,
+    source: Option<AccountSource>,
+    dry_run: bool,
+    plan_path: Option<PathBuf>,
+    resume: bool,
+) -> Result<Outcome> {
+    if direction == SyncDirection::MastodonToBluesky {
+        if plan_path.is_some() || resume || source.is_some() {
+            bail!(
+                "--plan, --resume and Bluesky sources are only supported when syncing from Bluesky to Mastodon"
+            );
+        }
+        return sync_to_bluesky(config_path, profile, dry_run).await;
+    }
+
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    if resume {
+        let checkpoint = FollowCheckpoint::resume(&checkpoint_path(&config))?;
+        let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+        ensure_same_mastodon_server(&config, &checkpoint.mastodon_server)?;
+
+        println!(
+            "Resuming sync started at {} with {} of {} account(s) left to follow",
+            format_timestamp(checkpoint.created_at).blue(),
+            checkpoint.remaining().len().yellow(),
+            checkpoint.followers.len()
+        );
+
+        let run = state.start_run("sync --resume");
+        let summary = follow_accounts(&mastodon_user, &config, checkpoint, &mut state, run).await?;
+        return Ok(summary.outcome());
+    }
+
+    let run = state.start_run("sync");
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+    let source = source.unwrap_or_else(|| AccountSource::Graph(config.follower_source()));
+    let statuses = get_follower_statuses(
+        &mastodon_user,
+        &bluesky,
+        &config,
+        &mut state,
+        &source,
+        false,
+    )
+    .await?;
+
+    // The run is only finished once the follows are done, a run interrupted before looks unfinished
+    state.record_statuses(run, &statuses);
+    state.save()?;
+
+    let ready_to_follow: Vec<_> = statuses
+        .into_iter()
+        .filter(|s| s.status == FollowerStatus::ReadyToFollow)
+        .collect();
+
+    if ready_to_follow.is_empty() {
+        println!("{}", "No new accounts to follow!".green());
+        state.finish_run(run);
+        state.save()?;
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Found {} new account(s) to follow",
+        ready_to_follow.len().yellow()
+    );
+
+    if dry_run {
+        println!(
+            "{}",
+            "Dry run, the following accounts would be followed:".dimmed()
+        );
+        for follower in &ready_to_follow {
+            println!("  {}", format!("@{}", follower.mastodon_acct).blue());
+        }
+        state.finish_run(run);
+        state.save()?;
+        return Ok(Outcome::Done);
+    }
+
+    if let Some(plan_path) = plan_path {
+        let mastodon_server = config.mastodon_server().unwrap_or_default().to_string();
+        let plan = FollowPlan::new(mastodon_server, ready_to_follow);
+        plan.write_to_file(&plan_path)?;
+        println!(
+            "Wrote plan with {} follow(s) to {}",
+            plan.followers.len().yellow(),
+            plan_path.display().blue()
+        );
+        println!(
+            "{}",
+            format!(
+                "Run `bridgy_followers apply {}` to follow them",
+                plan_path.display()
+            )
+            .dimmed()
+        );
+        state.finish_run(run);
+        state.save()?;
+        return Ok(Outcome::Done);
+    }
+
+    let checkpoint = FollowCheckpoint::new(
+        checkpoint_path(&config),
+        config.mastodon_server().unwrap_or_default().to_string(),
+        ready_to_follow,
+    );
+    let summary = follow_accounts(&mastodon_user, &config, checkpoint, &mut state, run).await?;
+
+    Ok(summary.outcome())
+}
+
+/// Sync every profile of the configuration one after the other, a failing profile doesn't stop the others
+pub async fn sync_all_profiles_command(
+    config_path: PathBuf,
+    direction: SyncDirection,
+    source: Option<AccountSource>,
+    dry_run: bool,
+) -> Result<Outcome> {
+    let config = Config::from_file(&config_path, None)?;
+    let profiles = config.profiles();
+    if profiles.is_empty() {
+        bail!("No profile configured, run `sync` once to configure the default profile");
+    }
+
+    let mut failed = Vec::new();
+    let mut outcome = Outcome::NothingToDo;
+    for profile in &profiles {
+        let name = profile.as_deref().unwrap_or("default");
+        println!();
+        println!("{} {}", "Profile".bold(), name.blue());
+
+        let result = sync_command(
+            config_path.clone(),
+            profile.as_deref(),
+            direction,
+            source.clone(),
+            dry_run,
+            None,
+            false,
+        )
+        .await;
+        match result {
+            Ok(profile_outcome) => outcome = outcome.merge(profile_outcome),
+            Err(e) => {
+                eprintln!("  Error: {}", e.to_string().red());
+                failed.push(name);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        let message = format!(
+            "Sync failed for {} of {} profile(s): {}",
+            failed.len(),
+            profiles.len(),
+            failed.join(", ")
+        );
+        if failed.len() == profiles.len() {
+            bail!(message);
+        }
+        eprintln!();
+        eprintln!("{}", message.red());
+        return Ok(Outcome::PartialFailure);
+    }
+
+    Ok(outcome)
+}
+
+/// Follow on Bluesky the bridged accounts of the people followed on Mastodon
+async fn sync_to_bluesky(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    dry_run: bool,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let run = state.start_run("sync --direction mastodon-to-bluesky");
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+    let statuses = get_fediverse_statuses(&mastodon_user, &bluesky, &config, false).await?;
+
+    state.record_accounts_seen(run, statuses.len());
+    state.save()?;
+
+    let ready_to_follow: Vec<_> = statuses
+        .into_iter()
+        .filter(|s| s.status == FollowerStatus::ReadyToFollow)
+        .collect();
+
+    if ready_to_follow.is_empty() {
+        println!("{}", "No new accounts to follow on Bluesky!".green());
+        state.finish_run(run);
+        state.save()?;
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Found {} new account(s) to follow on Bluesky",
+        ready_to_follow.len().yellow()
+    );
+
+    if dry_run {
+        println!(
+            "{}",
+            "Dry run, the following accounts would be followed:".dimmed()
+        );
+        for account in &ready_to_follow {
+            println!(
+                "  {} {}",
+                format!("@{}", account.bluesky_handle).blue(),
+                format!("(@{})", account.mastodon_acct).dimmed()
+            );
+        }
+        state.finish_run(run);
+        state.save()?;
+        return Ok(Outcome::Done);
+    }
+
+    let mut success_count = 0;
+    let mut error_count = 0;
+
+    for account in ready_to_follow {
+        print!(
+            "Following {}... ",
+            format!("@{}", account.bluesky_handle).blue()
+        );
+
+        let result = match &account.did {
+            Some(did) => bluesky::follow_account(&bluesky, did).await,
+            None => Err(eyre!("Unknown DID for {}", account.bluesky_handle)),
+        };
+        match result {
+            Ok(()) => {
+                println!("{}", "✓".green());
+                state.record_bluesky_follow(run, &account, FollowOutcome::Followed);
+                success_count += 1;
+            }
+            Err(e) => {
+                println!("{}", "✗".red());
+                eprintln!("  Error: {}", e.to_string().red());
+                state.record_bluesky_follow(
+                    run,
+                    &account,
+                    FollowOutcome::Failed {
+                        error: e.to_string(),
+                    },
+                );
+                error_count += 1;
+            }
+        }
+        state.save()?;
+    }
+    state.finish_run(run);
+    state.save()?;
+
+    println!();
+    println!(
+        "Successfully followed {} account(s)",
+        success_count.to_string().green()
+    );
+    if error_count > 0 {
+        println!(
+            "Failed to follow {} account(s)",
+            error_count.to_string().red()
+        );
+        return Ok(Outcome::PartialFailure);
+    }
+
+    Ok(Outcome::Done)
+}
+
+pub async fn apply_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    plan_path: &Path,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let plan = FollowPlan::from_file(plan_path)?;
+    plan.ensure_fresh(config.plan_max_age()?)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+
+    ensure_same_mastodon_server(&config, &plan.mastodon_server)?;
+
+    let ready_to_follow: Vec<_> = plan.ready_to_follow().cloned().collect();
+    if ready_to_follow.is_empty() {
+        println!("{}", "Plan contains no accounts to follow!".green());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Applying plan created at {} with {} account(s) to follow",
+        plan.created_at.blue(),
+        ready_to_follow.len().yellow()
+    );
+
+    let run = state.start_run("apply");
+    let checkpoint = FollowCheckpoint::new(
+        checkpoint_path(&config),
+        config.mastodon_server().unwrap_or_default().to_string(),
+        ready_to_follow,
+    );
+    let summary = follow_accounts(&mastodon_user, &config, checkpoint, &mut state, run).await?;
+
+    Ok(summary.outcome())
+}
+
+/// Id of the Mastodon list followed accounts are added to, if one is configured. The list is optional, failing to get
+/// it doesn't prevent following.
+async fn configured_list(mastodon_user: &Mastodon, config: &Config) -> Option<String> {
+    let title = config.mastodon_list()?;
+    match mastodon::get_or_create_list(mastodon_user, title).await {
+        Ok(list_id) => Some(list_id),
+        Err(e) => {
+            warn!("Failed to get the Mastodon list '{title}', accounts won't be added to it: {e}");
+            None
+        }
+    }
+}
+
+/// Fail if the configured Mastodon server isn't the one that some saved work was computed for
+fn ensure_same_mastodon_server(config: &Config, expected_server: &str) -> Result<()> {
+    if config.mastodon_server() != Some(expected_server) {
+        bail!(
+            "Expected Mastodon server '{}' but the configured server is '{}'",
+            expected_server,
+            config.mastodon_server().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Follow all the remaining accounts of a checkpoint on Mastodon, reporting progress and errors as we go and
+/// recording the outcomes in the state.
+///
+/// The checkpoint and the state are saved after each follow so that an interrupted run can be resumed with
+/// `sync --resume`, the checkpoint is kept while some follows failed so that they can be retried the same way.
+/// Followed accounts are also added to the configured Mastodon list, if any.
+async fn follow_accounts(
+    mastodon_user: &MastodonClient,
+    config: &Config,
+    mut checkpoint: FollowCheckpoint,
+    state: &mut State,
+    run: RunId,
+) -> Result<FollowSummary> {
+    let mut success_count = 0;
+    let mut error_count = 0;
+
+    let list_id = configured_list(mastodon_user, config).await;
+
+    checkpoint.save()?;
+    let mut executor = FollowExecutor::new(mastodon_user);
+
+    for follower in checkpoint.remaining() {
+        let mastodon_handle = &follower.mastodon_acct;
+        executor.wait_for_rate_limit().await;
+        print!("Following {}... ", format!("@{}", mastodon_handle).blue());
+
+        let options = config.follow_options_for(follower.handle.as_str(), follower.did.as_str());
+        match executor.follow(mastodon_handle, &options).await {
+            Ok(followed) => {
+                println!("{}", "✓".green());
+                state.record_follow(run, &follower, FollowOutcome::Followed);
+                state.record_mastodon_id(&follower, &followed.account_id);
+                checkpoint.mark_completed(&follower)?;
+                success_count += 1;
+
+                if let Some(list_id) = &list_id
+                    && let Err(e) = mastodon::add_accounts_to_list(
+                        mastodon_user,
+                        list_id,
+                        vec![followed.account_id],
+                    )
+                    .await
+                {
+                    eprintln!("  Failed to add to list: {}", e.to_string().red());
+                }
+            }
+            Err(e) => {
+                println!("{}", "✗".red());
+                eprintln!("  Error: {}", e.to_string().red());
+                state.record_follow(
+                    run,
+                    &follower,
+                    FollowOutcome::Failed {
+                        error: e.to_string(),
+                    },
+                );
+                error_count += 1;
+            }
+        }
+        // Saved with the checkpoint so that an interrupted run keeps the outcomes recorded so far
+        state.save()?;
+    }
+
+    println!();
+    println!(
+        "Successfully followed {} account(s)",
+        success_count.to_string().green()
+    );
+    if error_count > 0 {
+        println!(
+            "Failed to follow {} account(s)",
+            error_count.to_string().red()
+        );
+    }
+
+    state.finish_run(run);
+    state.save()?;
+    // Failed follows aren't completed, keep them in the checkpoint so that `sync --resume` retries them
+    if checkpoint.remaining().is_empty() {
+        checkpoint.remove()?;
+    } else {
+        println!(
+            "{}",
+            "Run `bridgy_followers sync --resume` to retry the failed follows".dimmed()
+        );
+    }
+
+    Ok(FollowSummary {
+        followed: success_count,
+        failed: error_count,
+    })
+}
+
+pub async fn watch_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    interval_minutes: Option<u64>,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let (mut interval, mut jitter) = watch_delays(&config, interval_minutes)?;
+    let mut shutdown = ShutdownSignal::new()?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    // The sessions are kept alive between cycles
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let mut bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+
+    println!(
+        "Watching for new accounts to follow, press {} to stop",
+        "Ctrl+C".blue()
+    );
+
+    // A failed cycle makes the whole run a partial failure, the next cycles still run
+    let mut outcome = Outcome::NothingToDo;
+    loop {
+        // Reload the configuration so that changes (e.g. to the ignore list) are picked up without a restart, the
+        // previous configuration is kept until an invalid one is fixed
+        let reloaded = Config::from_file(&config_path, profile).and_then(|reloaded| {
+            let delays = watch_delays(&reloaded, interval_minutes)?;
+            Ok((reloaded, delays))
+        });
+        match reloaded {
+            Ok((reloaded, delays)) => {
+                config = reloaded;
+                (interval, jitter) = delays;
+            }
+            Err(e) => {
+                error!("Failed to reload the configuration, keeping the previous one: {e:?}");
+                outcome = Outcome::PartialFailure;
+            }
+        }
+
+        // Cycles run one after the other, the next one is only scheduled once this one completes
+        let started = Instant::now();
+        let cycle =
+            match bluesky::ensure_session(&mut bluesky, &credential_builder, &mut config).await {
+                Ok(()) => watch_cycle(&mastodon_user, &bluesky, &config).await,
+                Err(e) => Err(e.wrap_err("Failed to log in to Bluesky again")),
+            };
+        match cycle {
+            Ok(summary) => {
+                info!(
+                    accounts_seen = summary.accounts_seen,
+                    ready_to_follow = summary.ready_to_follow,
+                    followed = summary.follows.followed,
+                    failed = summary.follows.failed,
+                    "Sync cycle completed in {}s",
+                    started.elapsed().as_secs()
+                );
+                if summary.ready_to_follow > 0 {
+                    outcome = outcome.merge(summary.follows.outcome());
+                }
+            }
+            Err(e) => {
+                error!("Sync cycle failed: {e:?}");
+                outcome = Outcome::PartialFailure;
+            }
+        }
+
+        let delay = next_delay(interval, jitter);
+        println!(
+            "{}",
+            format!(
+                "Next sync at {}",
+                format_timestamp(Timestamp::now() + delay)
+            )
+            .dimmed()
+        );
+
+        tokio::select! {
+            () = shutdown.recv() => {
+                println!("Shutting down");
+                break;
+            }
+            () = tokio::time::sleep(delay) => {}
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// The interval between two syncs of `watch` and its maximum jitter, `interval_minutes` overriding the configuration
+fn watch_delays(config: &Config, interval_minutes: Option<u64>) -> Result<(Duration, Duration)> {
+    let interval = match interval_minutes {
+        Some(minutes) => watch_delay("--interval", minutes, 1)?,
+        None => config.watch_interval()?,
+    };
+    Ok((interval, config.watch_jitter()?))
+}
+
+/// Summary of a single sync cycle of `watch`
+struct WatchCycleSummary {
+    accounts_seen: usize,
+    ready_to_follow: usize,
+    follows: FollowSummary,
+}
+
+async fn watch_cycle(
+    mastodon_user: &MastodonClient,
+    bluesky: &BlueskyAgent,
+    config: &Config,
+) -> Result<WatchCycleSummary> {
+    let mut state = State::for_config(config)?;
+    let run = state.start_run("watch");
+
+    let statuses = get_follower_statuses(
+        mastodon_user,
+        bluesky,
+        config,
+        &mut state,
+        &AccountSource::Graph(config.follower_source()),
+        true,
+    )
+    .await?;
+    state.record_statuses(run, &statuses);
+    state.save()?;
+
+    let accounts_seen = statuses.len();
+    let ready_to_follow: Vec<_> = statuses
+        .into_iter()
+        .filter(|s| s.status == FollowerStatus::ReadyToFollow)
+        .collect();
+    let ready_count = ready_to_follow.len();
+
+    let follows = if ready_to_follow.is_empty() {
+        state.finish_run(run);
+        state.save()?;
+        FollowSummary::default()
+    } else {
+        let checkpoint = FollowCheckpoint::new(
+            checkpoint_path(config),
+            config.mastodon_server().unwrap_or_default().to_string(),
+            ready_to_follow,
+        );
+        follow_accounts(mastodon_user, config, checkpoint, &mut state, run).await?
+    };
+
+    println!(
+        "{} {} account(s) checked, {} followed, {} failed",
+        format_timestamp(Timestamp::now()).dimmed(),
+        accounts_seen,
+        follows.followed.green(),
+        follows.failed.red()
+    );
+
+    Ok(WatchCycleSummary {
+        accounts_seen,
+        ready_to_follow: ready_count,
+        follows,
+    })
+}
+
+pub async fn backfill_list_command(config_path: PathBuf, profile: Option<&str>) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+
+    let Some(list_title) = config.mastodon_list().map(ToString::to_string) else {
+        bail!("No Mastodon list configured, set `mastodon_list` in the configuration");
+    };
+
+    let credential_builder = credentials::credential_builder(&config)?;
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+
+    let list_id = mastodon::get_or_create_list(&mastodon_user, &list_title).await?;
+    let in_list: HashSet<String> = mastodon::get_list_account_ids(&mastodon_user, &list_id)
+        .await?
+        .into_iter()
+        .collect();
+
+    let following = mastodon::get_following_accounts(&mastodon_user, false).await?;
+    let to_add: Vec<_> = following
+        .into_iter()
+        .filter(|account| mastodon_acct_to_bluesky_handle(&account.acct).is_some())
+        .filter(|account| !in_list.contains(&account.id))
+        .collect();
+
+    if to_add.is_empty() {
+        println!(
+            "{}",
+            format!("All bridged accounts are already in list '{list_title}'!").green()
+        );
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Adding {} bridged account(s) to list {}",
+        to_add.len().yellow(),
+        list_title.blue()
+    );
+
+    // Mastodon accepts multiple accounts per request
+    for chunk in to_add.chunks(50) {
+        let account_ids = chunk.iter().map(|account| account.id.clone()).collect();
+        mastodon::add_accounts_to_list(&mastodon_user, &list_id, account_ids).await?;
+        for account in chunk {
+            println!(
+                "  {} {}",
+                "✓".green(),
+                format!("@{}", account.acct).dimmed()
+            );
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+pub async fn csv_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    output_path: Option<PathBuf>,
+    source: Option<FollowerSource>,
+) -> Result<()> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+    let run = state.start_run("csv");
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+    let source = AccountSource::Graph(source.unwrap_or_else(|| config.follower_source()));
+    let statuses =
+        get_follower_statuses(&mastodon_user, &bluesky, &config, &mut state, &source, true).await?;
+
+    state.record_statuses(run, &statuses);
+    state.finish_run(run);
+    state.save()?;
+
+    let csv = statuses_to_import_csv(&statuses, &config)?;
+    println!("{}", csv);
+
+    if let Some(output_path) = output_path {
+        fs::write(&output_path, csv)?;
+        println!("Wrote output to {}", output_path.display().blue());
+    }
+
+    Ok(())
+}
+
+pub async fn report_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    format: ReportFormat,
+    output_path: Option<PathBuf>,
+    source: Option<FollowerSource>,
+) -> Result<()> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+    let run = state.start_run("report");
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+    let source = AccountSource::Graph(source.unwrap_or_else(|| config.follower_source()));
+    let statuses =
+        get_follower_statuses(&mastodon_user, &bluesky, &config, &mut state, &source, true).await?;
+
+    state.record_statuses(run, &statuses);
+    state.finish_run(run);
+    state.save()?;
+
+    let report = statuses_to_report(&statuses, format)?;
+
+    // Only the report itself goes to stdout so that it can be piped to other tools
+    if let Some(output_path) = output_path {
+        fs::write(&output_path, report)?;
+        eprintln!("Wrote report to {}", output_path.display().blue());
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+/// Print the result of a single check of `explain`
+fn print_check(name: &str, passed: bool, details: &str) {
+    let mark = if passed {
+        "✓".green().to_string()
+    } else {
+        "✗".red().to_string()
+    };
+    println!("  {mark} {}: {details}", name.bold());
+}
+
+pub async fn explain_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    account: &str,
+) -> Result<()> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+
+    let account = account.trim_start_matches('@');
+    let actor = if account.starts_with("did:") {
+        Did::new(account.to_string())
+            .map(AtIdentifier::Did)
+            .map_err(|e| eyre!("Invalid DID '{account}': {e}"))?
+    } else {
+        Handle::new(account.to_string())
+            .map(AtIdentifier::Handle)
+            .map_err(|e| eyre!("Invalid handle '{account}': {e}"))?
+    };
+
+    let Some(bluesky_profile) = bluesky::get_profiles(&bluesky, [actor]).await?.pop() else {
+        println!("{}", account.blue());
+        print_check("Bluesky profile", false, "account not found");
+        println!();
+        println!(
+            "Status: {}",
+            FollowerStatus::NotBridged(NotBridgedReason::NoAccountOnBluesky)
+                .to_string()
+                .yellow()
+        );
+        return Ok(());
+    };
+    let handle = bluesky_profile.handle.clone();
+    let did = bluesky_profile.did.clone();
+    let mastodon_acct = bluesky_handle_to_mastodon(&handle);
+
+    println!("{} ({})", handle.as_str().blue(), did.as_str().dimmed());
+    println!("Mastodon account: {}", format!("@{mastodon_acct}").blue());
+    println!();
+
+    // Every check is run even once the status is decided, the status is the one of the first failing check in the
+    // order used by `get_follower_statuses`
+    let mut status = None;
+
+    // Ignore list
+    let ignored = config.is_ignored(Some(did.as_str()), handle.as_str());
+    if ignored {
+        print_check("Ignore list", false, "ignored");
+        status.get_or_insert(FollowerStatus::Ignored);
+    } else {
+        print_check("Ignore list", true, "not ignored");
+    }
+
+    // The Mastodon account is looked up once, by the id recorded in the state if any as the handle may have changed
+    let lookup = mastodon::lookup_account(&mastodon_user, &mastodon_acct).await;
+    let mastodon_id = state
+        .mastodon_id(&did)
+        .map(ToString::to_string)
+        .or_else(|| lookup.as_ref().ok().map(|account| account.id.clone()));
+
+    // Mastodon following
+    match &mastodon_id {
+        Some(mastodon_id) => {
+            let relationship = mastodon::get_relationship(&mastodon_user, mastodon_id).await?;
+            if relationship.following {
+                print_check(
+                    "Mastodon following",
+                    false,
+                    &format!("already followed (account id {mastodon_id})"),
+                );
+                status.get_or_insert(FollowerStatus::AlreadyFollowedOnMastodon);
+            } else {
+                print_check(
+                    "Mastodon following",
+                    true,
+                    &format!("not followed (account id {mastodon_id})"),
+                );
+            }
+        }
+        None => print_check(
+            "Mastodon following",
+            true,
+            "not followed (account unknown to the Mastodon server)",
+        ),
+    }
+
+    // Mastodon moderation
+    match mastodon::get_moderation(&mastodon_user).await? {
+        Some(moderation) => {
+            let moderation_status = if moderation.blocks_domain(BRIDGY_MASTODON_DOMAIN) {
+                Some(FollowerStatus::DomainBlocked)
+            } else if moderation.blocked.contains(&mastodon_acct) {
+                Some(FollowerStatus::BlockedOnMastodon)
+            } else if moderation.muted.contains(&mastodon_acct) {
+                Some(FollowerStatus::MutedOnMastodon)
+            } else {
+                None
+            };
+            match moderation_status {
+                Some(moderation_status) => {
+                    print_check("Mastodon moderation", false, &moderation_status.to_string());
+                    status.get_or_insert(moderation_status);
+                }
+                None => print_check("Mastodon moderation", true, "not blocked or muted"),
+            }
+        }
+        None => print_check(
+            "Mastodon moderation",
+            true,
+            "unknown, the access token can't read blocks and mutes",
+        ),
+    }
+
+    // Relationship with the bridge on Bluesky
+    let bridgy_did = bluesky::get_bridgy_did(&bluesky).await?;
+    let relationships =
+        bluesky::get_relationships(&bluesky, bridgy_did.into(), [did.clone().into()]).await?;
+    match relationships.get(&did) {
+        None => {
+            print_check("Bridge relationship", false, "no relationship data");
+            status.get_or_insert(FollowerStatus::NotBridged(
+                NotBridgedReason::NoRelationshipData,
+            ));
+        }
+        Some(relationship) if blocks_actor(relationship) => {
+            print_check(
+                "Bridge relationship",
+                false,
+                "blocks the bridge, directly or via a block list",
+            );
+            status.get_or_insert(FollowerStatus::NotBridged(NotBridgedReason::BlocksBridge));
+        }
+        Some(relationship) if relationship.followed_by.is_none() => {
+            print_check("Bridge relationship", false, "doesn't follow the bridge");
+            status.get_or_insert(FollowerStatus::NotBridged(
+                NotBridgedReason::NotFollowingBridge,
+            ));
+        }
+        Some(_) => print_check(
+            "Bridge relationship",
+            true,
+            "follows the bridge and doesn't block it",
+        ),
+    }
+
+    // WebFinger on the bridge
+    let http_client = webfinger::create_client(config.webfinger_timeout(), 1)?;
+    match webfinger::account_exists(&http_client, BRIDGY_ACTIVITY_PUB_URL, &mastodon_acct).await {
+        Ok(true) => print_check("Bridge WebFinger", true, "bridged account found"),
+        Ok(false) => {
+            print_check("Bridge WebFinger", false, "bridged account not found");
+            status.get_or_insert(FollowerStatus::NotBridged(
+                NotBridgedReason::NoAccountOnBridgy,
+            ));
+        }
+        Err(e) => {
+            print_check("Bridge WebFinger", false, &format!("{e}"));
+            status.get_or_insert(FollowerStatus::Unverified);
+        }
+    }
+
+    // Mastodon lookup, needed to follow the account
+    match &lookup {
+        Ok(account) => print_check(
+            "Mastodon lookup",
+            true,
+            &format!("found as @{} (account id {})", account.acct, account.id),
+        ),
+        Err(e) => print_check("Mastodon lookup", false, &format!("{e}")),
+    }
+
+    let status = status.unwrap_or(FollowerStatus::ReadyToFollow);
+    println!();
+    println!("Status: {}", status.to_string().yellow());
+    if status == FollowerStatus::ReadyToFollow && lookup.is_err() {
+        println!(
+            "{}",
+            "The account is bridged but the Mastodon server can't find it yet, following it will fail.".yellow()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn reconcile_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    include_manual: bool,
+    yes: bool,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+
+    println!("Fetching follows from Bluesky...");
+    let own_did = bluesky::get_own_did(&bluesky).await?;
+    let bluesky_follows = bluesky::get_follows(&bluesky, &own_did).await?;
+    if bluesky_follows.is_empty() {
+        bail!("No follows found on Bluesky, refusing to unfollow every bridged account");
+    }
+    let bluesky_handles: HashSet<String> = bluesky_follows
+        .values()
+        .map(|profile| profile.handle.as_str().to_lowercase())
+        .collect();
+    let bluesky_dids: HashSet<&str> = bluesky_follows.keys().map(|did| did.as_str()).collect();
+
+    let mastodon_following = mastodon::get_following_accounts(&mastodon_user, false).await?;
+
+    let mut to_unfollow = Vec::new();
+    let mut manual_follows = 0;
+    for account in mastodon_following {
+        let Some(handle) = mastodon_acct_to_bluesky_handle(&account.acct) else {
+            continue;
+        };
+        // The DID is known for accounts seen before, it still matches after a handle change
+        let known_did = state
+            .find_by_mastodon_id(&account.id)
+            .map(|(did, _)| did.to_string());
+        if bluesky_handles.contains(&handle)
+            || known_did
+                .as_deref()
+                .is_some_and(|did| bluesky_dids.contains(did))
+        {
+            continue;
+        }
+
+        if config.is_ignored(known_did.as_deref(), &handle) {
+            info!("User '{handle}' in ignore list, keeping the Mastodon follow");
+            continue;
+        }
+
+        let acct = account.acct.to_lowercase();
+        let created_by_tool = state.followed_by_tool(&acct, &account.id);
+        if !created_by_tool && !include_manual {
+            info!("User '{handle}' was followed manually on Mastodon, keeping the follow");
+            manual_follows += 1;
+            continue;
+        }
+
+        to_unfollow.push((account, created_by_tool));
+    }
+    to_unfollow.sort_by(|(a, _), (b, _)| a.acct.cmp(&b.acct));
+
+    if manual_follows > 0 {
+        println!(
+            "{}",
+            format!(
+                "Skipping {manual_follows} account(s) followed manually on Mastodon (use --include-manual to include them)"
+            )
+            .dimmed()
+        );
+    }
+
+    if to_unfollow.is_empty() {
+        println!("{}", "No bridged accounts to unfollow!".green());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Found {} bridged account(s) no longer followed on Bluesky",
+        to_unfollow.len().yellow()
+    );
+
+    let selected: Vec<_> = if yes {
+        to_unfollow.iter().collect()
+    } else {
+        interactive::require_selection("pass --yes to apply every change")?;
+        let items: Vec<String> = to_unfollow
+            .iter()
+            .map(|(account, created_by_tool)| {
+                let origin = if *created_by_tool {
+                    "followed by bridgy_followers"
+                } else {
+                    "followed manually"
+                };
+                format!("@{} {}", account.acct, format!("({origin})").dimmed())
+            })
+            .collect();
+
+        println!();
+        println!("Select accounts to unfollow on Mastodon:");
+        println!("{}", "(Space to select, Enter to confirm)".dimmed());
+        println!();
+
+        let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+            .items(&items)
+            .defaults(&vec![true; items.len()])
+            .interact()?;
+
+        selections
+            .into_iter()
+            .map(|idx| &to_unfollow[idx])
+            .collect()
+    };
+
+    if selected.is_empty() {
+        println!("{}", "No changes made.".yellow());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    let run = state.start_run("reconcile");
+    let mut success_count = 0;
+    let mut error_count = 0;
+
+    for (account, _) in selected {
+        print!("Unfollowing {}... ", format!("@{}", account.acct).blue());
+
+        match mastodon::unfollow_account(&mastodon_user, &account.id).await {
+            Ok(()) => {
+                println!("{}", "✓".green());
+                state.record_unfollow(run, &account.acct.to_lowercase());
+                success_count += 1;
+            }
+            Err(e) => {
+                println!("{}", "✗".red());
+                eprintln!("  Error: {}", e.to_string().red());
+                error_count += 1;
+            }
+        }
+    }
+
+    state.finish_run(run);
+    state.save()?;
+
+    println!();
+    println!(
+        "Successfully unfollowed {} account(s)",
+        success_count.to_string().green()
+    );
+    if error_count > 0 {
+        println!(
+            "Failed to unfollow {} account(s)",
+            error_count.to_string().red()
+        );
+        return Ok(Outcome::PartialFailure);
+    }
+    if success_count == 0 {
+        return Ok(Outcome::NothingToDo);
+    }
+
+    Ok(Outcome::Done)
+}
+
+pub async fn prune_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    unfollow: bool,
+    yes: bool,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
+
+    let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
+    let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
+
+    let mastodon_following = mastodon::get_following_accounts(&mastodon_user, false).await?;
+    let bridged: Vec<_> = mastodon_following
+        .into_iter()
+        .filter_map(|account| {
+            let handle = mastodon_acct_to_bluesky_handle(&account.acct)?;
+            Some((handle, account))
+        })
+        .collect();
+
+    println!(
+        "Checking {} followed bridged account(s) with the bridge...",
+        bridged.len()
+    );
+
+    // Find the DID of each account from its handle, or from the state for accounts that changed handle
+    let actors = bridged
+        .iter()
+        .filter_map(|(handle, _)| Handle::new(handle.clone()).ok())
+        .map(AtIdentifier::Handle);
+    let profiles: HashMap<String, (Handle, Did)> = bluesky::get_profiles(&bluesky, actors)
+        .await?
+        .into_iter()
+        .map(|profile| {
+            (
+                profile.handle.as_str().to_lowercase(),
+                (profile.handle.clone(), profile.did.clone()),
+            )
+        })
+        .collect();
+
+    let mut accounts = HashMap::new();
+    let mut candidates = Vec::new();
+    let mut unknown_count = 0;
+    for (handle, account) in bridged {
+        let known = profiles.get(&handle).cloned().or_else(|| {
+            let (did, history) = state.find_by_mastodon_id(&account.id)?;
+            Some((history.handle.clone(), Did::new(did.to_string()).ok()?))
+        });
+        let Some((handle, did)) = known else {
+            info!(
+                "Couldn't find the Bluesky account behind '{}'",
+                account.acct
+            );
+            unknown_count += 1;
+            continue;
+        };
+
+        if config.is_ignored(Some(did.as_str()), handle.as_str()) {
+            info!("User '{handle}' in ignore list, keeping the Mastodon follow");
+            continue;
+        }
+
+        candidates.push(BlueskyAccount {
+            handle,
+            did: did.clone(),
+        });
+        accounts.insert(did, account);
+    }
+
+    let bridgy_did = bluesky::get_bridgy_did(&bluesky).await?;
+    let mut statuses = check_bridged(&bluesky, &config, &bridgy_did, candidates).await?;
+
+    // Accounts that are still bridged are followed already, record them as such
+    for follower in &mut statuses {
+        if follower.status == FollowerStatus::ReadyToFollow {
+            follower.status = FollowerStatus::AlreadyFollowedOnMastodon;
+        }
+    }
+    let run = state.start_run("prune");
+    state.record_statuses(run, &statuses);
+
+    let mut not_bridged: Vec<_> = statuses
+        .into_iter()
+        .filter(|follower| matches!(follower.status, FollowerStatus::NotBridged(_)))
+        .filter_map(|follower| {
+            let account = accounts.remove(&follower.did)?;
+            Some((follower, account))
+        })
+        .collect();
+    not_bridged.sort_by(|(a, _), (b, _)| a.mastodon_acct.cmp(&b.mastodon_acct));
+
+    if unknown_count > 0 {
+        println!(
+            "{}",
+            format!(
+                "Couldn't find the Bluesky account behind {unknown_count} bridged account(s), they may have changed handle (see `repair`)"
+            )
+            .dimmed()
+        );
+    }
+
+    if not_bridged.is_empty() {
+        state.finish_run(run);
+        state.save()?;
+        println!(
+            "{}",
+            "All followed bridged accounts are still bridged!".green()
+        );
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Found {} followed account(s) that stopped bridging:",
+        not_bridged.len().yellow()
+    );
+    for (follower, account) in &not_bridged {
+        println!(
+            "  {} {}",
+            format!("@{}", account.acct).blue(),
+            format!("({})", follower.status).dimmed()
+        );
+    }
+
+    if !unfollow {
+        state.finish_run(run);
+        state.save()?;
+        println!();
+        println!("{}", "Run with --unfollow to unfollow them.".dimmed());
+        return Ok(Outcome::Done);
+    }
+
+    let selected: Vec<_> = if yes {
+        not_bridged.iter().collect()
+    } else {
+        interactive::require_selection("pass --yes to apply every change")?;
+        let items: Vec<String> = not_bridged
+            .iter()
+            .map(|(_, account)| format!("@{}", account.acct))
+            .collect();
+
+        println!();
+        println!("Select accounts to unfollow on Mastodon:");
+        println!("{}", "(Space to select, Enter to confirm)".dimmed());
+        println!();
+
+        let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+            .items(&items)
+            .defaults(&vec![true; items.len()])
+            .interact()?;
+
+        selections
+            .into_iter()
+            .map(|idx| &not_bridged[idx])
+            .collect()
+    };
+
+    let mut success_count = 0;
+    let mut error_count = 0;
+
+    for (_, account) in selected {
+        print!("Unfollowing {}... ", format!("@{}", account.acct).blue());
+
+        match mastodon::unfollow_account(&mastodon_user, &account.id).await {
+            Ok(()) => {
+                println!("{}", "✓".green());
+                state.record_unfollow(run, &account.acct.to_lowercase());
+                success_count += 1;
+            }
+            Err(e) => {
+                println!("{}", "✗".red());
+                eprintln!("  Error: {}", e.to_string().red());
+                error_count += 1;
+            }
+        }
+    }
+
+    state.finish_run(run);
+    state.save()?;
+
+    println!();
+    println!(
+        "Successfully unfollowed {} account(s)",
+        success_count.to_string().green()
+    );
+    if error_count > 0 {
+        println!(
+            "Failed to unfollow {} account(s)",
+            error_count.to_string().red()
+        );
+        return Ok(Outcome::PartialFailure);
+    }
+    if success_count == 0 {
+        return Ok(Outcome::NothingToDo);
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// A Mastodon follow of a bridged account under a handle that the Bluesky account doesn't use anymore
+struct StaleFollow {
+    /// The followed account, under the old handle
+    account: Account,
+    /// The bridged account under the current handle
+    current: BridgedFollower,
+    /// Whether the bridged account under the current handle is already followed too
+    current_followed: bool,
+}
+
+pub async fn repair_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    yes: bool,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
+
+    let credential_builder = credentials::credential_builder(&config)?;
 
     let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
     let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
-    let statuses =
-        get_follower_statuses(&mastodon_user, &bluesky, config.ignored_accounts(), false).await?;
 
-    let ready_to_follow: Vec<_> = statuses
+    let mastodon_following = mastodon::get_following_accounts(&mastodon_user, false).await?;
+    let following_accts: HashSet<String> = mastodon_following
         .iter()
-        .filter(|s| s.status == FollowerStatus::ReadyToFollow)
+        .map(|account| account.acct.to_lowercase())
+        .collect();
+    let bridged: Vec<_> = mastodon_following
+        .into_iter()
+        .filter(|account| mastodon_acct_to_bluesky_handle(&account.acct).is_some())
         .collect();
 
-    if ready_to_follow.is_empty() {
-        println!("{}", "No new accounts to follow!".green());
-        return Ok(());
+    println!(
+        "Finding the Bluesky account behind {} bridged account(s)...",
+        bridged.len()
+    );
+    let concurrency = config.webfinger_concurrency();
+    let http_client = webfinger::create_client(config.webfinger_timeout(), concurrency)?;
+    let recovered: Vec<_> = stream::iter(bridged)
+        .map(|account| {
+            let state = &state;
+            let http_client = &http_client;
+            async move {
+                let did = recover_did(state, http_client, &account).await;
+                (account, did)
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let mut unknown_count = 0;
+    let with_did: Vec<_> = recovered
+        .into_iter()
+        .filter_map(|(account, did)| match did {
+            Some(did) => Some((account, did)),
+            None => {
+                info!("Couldn't find the DID of '{}'", account.acct);
+                unknown_count += 1;
+                None
+            }
+        })
+        .collect();
+
+    let current_handles: HashMap<Did, Handle> = bluesky::get_profiles(
+        &bluesky,
+        with_did
+            .iter()
+            .map(|(_, did)| AtIdentifier::Did(did.clone())),
+    )
+    .await?
+    .into_iter()
+    .map(|profile| (profile.did.clone(), profile.handle.clone()))
+    .collect();
+
+    let mut stale_follows = Vec::new();
+    for (account, did) in with_did {
+        let Some(handle) = current_handles.get(&did) else {
+            info!(
+                did = did.as_str(),
+                "Account '{}' doesn't exist on Bluesky anymore", account.acct
+            );
+            continue;
+        };
+        if handle.as_str() == INVALID_HANDLE {
+            info!(
+                did = did.as_str(),
+                "Account '{}' has no valid handle on Bluesky", account.acct
+            );
+            continue;
+        }
+
+        let current = BridgedFollower::new(handle.clone(), did, FollowerStatus::ReadyToFollow);
+        if current.mastodon_acct == account.acct.to_lowercase() {
+            continue;
+        }
+
+        info!(
+            did = current.did.as_str(),
+            "Account '{}' is now '{}'", account.acct, current.mastodon_acct
+        );
+        stale_follows.push(StaleFollow {
+            current_followed: following_accts.contains(&current.mastodon_acct),
+            account,
+            current,
+        });
+    }
+    stale_follows.sort_by(|a, b| a.account.acct.cmp(&b.account.acct));
+
+    if unknown_count > 0 {
+        println!(
+            "{}",
+            format!("Couldn't find the Bluesky account behind {unknown_count} bridged account(s)")
+                .dimmed()
+        );
+    }
+
+    if stale_follows.is_empty() {
+        println!("{}", "No stale follows found!".green());
+        return Ok(Outcome::NothingToDo);
     }
 
     println!(
-        "Found {} new account(s) to follow",
-        ready_to_follow.len().yellow()
+        "Found {} follow(s) of bridged accounts under an old handle",
+        stale_follows.len().yellow()
     );
 
+    let selected: Vec<_> = if yes {
+        stale_follows.iter().collect()
+    } else {
+        interactive::require_selection("pass --yes to apply every change")?;
+        let items: Vec<String> = stale_follows
+            .iter()
+            .map(|stale| {
+                let current = if stale.current_followed {
+                    format!("(@{} already followed)", stale.current.mastodon_acct)
+                } else {
+                    format!("(now @{})", stale.current.mastodon_acct)
+                };
+                format!("@{} {}", stale.account.acct, current.dimmed())
+            })
+            .collect();
+
+        println!();
+        println!("Select follows to repair:");
+        println!("{}", "(Space to select, Enter to confirm)".dimmed());
+        println!();
+
+        let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+            .items(&items)
+            .defaults(&vec![true; items.len()])
+            .interact()?;
+
+        selections
+            .into_iter()
+            .map(|idx| &stale_follows[idx])
+            .collect()
+    };
+
+    if selected.is_empty() {
+        println!("{}", "No changes made.".yellow());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    let list_id = configured_list(&mastodon_user, &config).await;
+
+    let run = state.start_run("repair");
+    let mut executor = FollowExecutor::new(&mastodon_user);
     let mut success_count = 0;
     let mut error_count = 0;
 
-    for follower in ready_to_follow {
-        let mastodon_handle = bluesky_handle_to_mastodon(&follower.handle);
-        print!("Following {}... ", format!("@{}", mastodon_handle).blue());
+    for stale in selected {
+        print!(
+            "Replacing {} with {}... ",
+            format!("@{}", stale.account.acct).blue(),
+            format!("@{}", stale.current.mastodon_acct).blue()
+        );
+
+        // Follow the current account first so that nothing is lost if it fails
+        if !stale.current_followed {
+            executor.wait_for_rate_limit().await;
+            let options = config
+                .follow_options_for(stale.current.handle.as_str(), stale.current.did.as_str());
+            match executor
+                .follow(&stale.current.mastodon_acct, &options)
+                .await
+            {
+                Ok(followed) => {
+                    state.record_follow(run, &stale.current, FollowOutcome::Followed);
+                    state.record_mastodon_id(&stale.current, &followed.account_id);
+
+                    if let Some(list_id) = &list_id
+                        && let Err(e) = mastodon::add_accounts_to_list(
+                            &mastodon_user,
+                            list_id,
+                            vec![followed.account_id],
+                        )
+                        .await
+                    {
+                        eprintln!("  Failed to add to list: {}", e.to_string().red());
+                    }
+                }
+                Err(e) => {
+                    println!("{}", "✗".red());
+                    eprintln!("  Error: {}", e.to_string().red());
+                    state.record_follow(
+                        run,
+                        &stale.current,
+                        FollowOutcome::Failed {
+                            error: e.to_string(),
+                        },
+                    );
+                    error_count += 1;
+                    continue;
+                }
+            }
+        }
 
-        match mastodon::follow_account(&mastodon_user, &mastodon_handle).await {
-            Ok(_) => {
+        match mastodon::unfollow_account(&mastodon_user, &stale.account.id).await {
+            Ok(()) => {
                 println!("{}", "✓".green());
+                state.record_unfollow(run, &stale.account.acct.to_lowercase());
                 success_count += 1;
             }
             Err(e) => {
@@ -54,54 +1561,420 @@ pub async fn sync_command(config_path: PathBuf, _output_path: Option<PathBuf>) -
         }
     }
 
+    state.finish_run(run);
+    state.save()?;
+
     println!();
     println!(
-        "Successfully followed {} account(s)",
+        "Successfully repaired {} follow(s)",
         success_count.to_string().green()
     );
     if error_count > 0 {
         println!(
-            "Failed to follow {} account(s)",
+            "Failed to repair {} follow(s)",
             error_count.to_string().red()
         );
+        return Ok(Outcome::PartialFailure);
+    }
+    if success_count == 0 {
+        return Ok(Outcome::NothingToDo);
     }
 
-    Ok(())
+    Ok(Outcome::Done)
+}
+
+/// Find the DID of the Bluesky account behind a bridged account on Mastodon.
+///
+/// The DID is known if the account was seen before, otherwise it's part of the profile URL or of the ActivityPub
+/// actor id returned by the WebFinger endpoint of the bridge.
+async fn recover_did(
+    state: &State,
+    http_client: &reqwest::Client,
+    account: &Account,
+) -> Option<Did> {
+    if let Some((did, _)) = state.find_by_mastodon_id(&account.id)
+        && let Ok(did) = Did::new(did.to_string())
+    {
+        return Some(did);
+    }
+
+    // The actor id and profile URL of a bridged account usually contain its DID
+    if let Some(did) = did_from_url(&account.uri).or_else(|| did_from_url(&account.url)) {
+        return Some(did);
+    }
+
+    // Otherwise the actor links to the Bluesky account. It's fetched by id rather than looked up by handle, which is
+    // stale for these accounts.
+    match activitypub::fetch_actor(http_client, &account.uri).await {
+        Ok(actor) => actor.identities().find_map(did_from_url),
+        Err(e) => {
+            info!("Failed to fetch the actor of '{}': {e}", account.acct);
+            None
+        }
+    }
+}
+
+/// A block or mute to apply or lift on Mastodon
+struct ModerationChange {
+    did: Did,
+    handle: Handle,
+    mastodon_acct: String,
+    /// Id of the account on Mastodon, only known when lifting a block or mute we applied
+    mastodon_id: Option<String>,
+    action: ModerationAction,
+}
+
+enum ModerationChangeOutcome {
+    /// The change was made on the account with this id
+    Applied { mastodon_id: String },
+    /// The account is already blocked or muted on Mastodon, such moderation was decided manually and isn't tracked so
+    /// that it's never lifted by us
+    AlreadyDone,
+    /// Mastodon doesn't know the bridged account, e.g. the Bluesky account isn't bridged
+    NotBridged,
 }
 
-pub async fn csv_command(config_path: PathBuf, output_path: Option<PathBuf>) -> Result<()> {
-    let mut config = Config::from_file(&config_path)?;
+pub async fn moderation_sync_command(
+    config_path: PathBuf,
+    profile: Option<&str>,
+    dry_run: bool,
+) -> Result<Outcome> {
+    let mut config = Config::from_file(&config_path, profile)?;
+    let mut state = State::for_config(&config)?;
 
-    let credential_builder = keyring::default::default_credential_builder();
+    let credential_builder = credentials::credential_builder(&config)?;
 
     let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
     let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
-    let statuses =
-        get_follower_statuses(&mastodon_user, &bluesky, config.ignored_accounts(), true).await?;
 
-    let csv = statuses_to_import_csv(&statuses)?;
-    println!("{}", csv);
+    println!("Fetching blocks and mutes from Bluesky...");
+    let blocks = bluesky::get_blocks(&bluesky).await?;
+    let mutes = bluesky::get_mutes(&bluesky).await?;
 
-    if let Some(output_path) = output_path {
-        fs::write(&output_path, csv)?;
-        println!("Wrote output to {}", output_path.display().blue());
+    let applied = &state.data().moderation;
+    let mut changes = Vec::new();
+
+    // Blocks and mutes on Bluesky that we didn't mirror yet
+    let wanted = [
+        (&blocks, ModerationAction::Block),
+        (&mutes, ModerationAction::Mute),
+    ];
+    for (profiles, action) in wanted {
+        for (did, profile) in profiles {
+            let already_applied = applied
+                .get(did.as_str())
+                .is_some_and(|applied| match action {
+                    ModerationAction::Block => applied.blocked_at.is_some(),
+                    _ => applied.muted_at.is_some(),
+                });
+            if !already_applied {
+                changes.push(ModerationChange {
+                    did: did.clone(),
+                    handle: profile.handle.clone(),
+                    mastodon_acct: bluesky_handle_to_mastodon(&profile.handle),
+                    mastodon_id: None,
+                    action,
+                });
+            }
+        }
+    }
+
+    // Blocks and mutes we mirrored that were lifted on Bluesky
+    for (did, applied) in applied {
+        let Ok(did) = Did::new(did.clone()) else {
+            continue;
+        };
+        let lifted = [
+            (
+                applied.blocked_at.is_some() && !blocks.contains_key(&did),
+                ModerationAction::Unblock,
+            ),
+            (
+                applied.muted_at.is_some() && !mutes.contains_key(&did),
+                ModerationAction::Unmute,
+            ),
+        ];
+        for (_, action) in lifted.into_iter().filter(|(lifted, _)| *lifted) {
+            changes.push(ModerationChange {
+                did: did.clone(),
+                handle: applied.handle.clone(),
+                mastodon_acct: applied.mastodon_acct.clone(),
+                mastodon_id: Some(applied.mastodon_id.clone()),
+                action,
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.mastodon_acct.cmp(&b.mastodon_acct));
+
+    if changes.is_empty() {
+        println!("{}", "Mastodon moderation already matches Bluesky!".green());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    println!(
+        "Found {} block(s) or mute(s) to apply or lift on Mastodon",
+        changes.len().yellow()
+    );
+
+    if dry_run {
+        println!(
+            "{}",
+            "Dry run, the following changes would be made:".dimmed()
+        );
+        for change in &changes {
+            println!(
+                "  {} {}",
+                change.action,
+                format!("@{}", change.mastodon_acct).blue()
+            );
+        }
+        return Ok(Outcome::Done);
+    }
+
+    let run = state.start_run("moderation sync");
+    let mut success_count = 0;
+    let mut skipped_count = 0;
+    let mut not_bridged_count = 0;
+    let mut error_count = 0;
+
+    for change in changes {
+        print!(
+            "{} {}... ",
+            change.action,
+            format!("@{}", change.mastodon_acct).blue()
+        );
+
+        match apply_moderation_change(&mastodon_user, &change).await {
+            Ok(ModerationChangeOutcome::Applied { mastodon_id }) => {
+                println!("{}", "✓".green());
+                state.record_moderation(
+                    run,
+                    &change.did,
+                    &change.handle,
+                    &change.mastodon_acct,
+                    &mastodon_id,
+                    change.action,
+                );
+                success_count += 1;
+            }
+            Ok(ModerationChangeOutcome::AlreadyDone) => {
+                println!("{}", "already done on Mastodon, skipping".dimmed());
+                skipped_count += 1;
+            }
+            Ok(ModerationChangeOutcome::NotBridged) => {
+                println!("{}", "not bridged, skipping".dimmed());
+                not_bridged_count += 1;
+            }
+            Err(e) => {
+                println!("{}", "✗".red());
+                eprintln!("  Error: {}", e.to_string().red());
+                error_count += 1;
+            }
+        }
+    }
+
+    state.finish_run(run);
+    state.save()?;
+
+    println!();
+    println!(
+        "Successfully applied {} change(s)",
+        success_count.to_string().green()
+    );
+    if skipped_count > 0 {
+        println!(
+            "Skipped {} account(s) already blocked or muted manually on Mastodon",
+            skipped_count.to_string().yellow()
+        );
+    }
+    if not_bridged_count > 0 {
+        println!(
+            "Skipped {} account(s) not bridged to Mastodon",
+            not_bridged_count.to_string().yellow()
+        );
+    }
+    if error_count > 0 {
+        println!(
+            "Failed to apply {} change(s)",
+            error_count.to_string().red()
+        );
+        return Ok(Outcome::PartialFailure);
+    }
+    if success_count == 0 {
+        return Ok(Outcome::NothingToDo);
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// Apply a block or mute change on Mastodon
+async fn apply_moderation_change(
+    mastodon_user: &Mastodon,
+    change: &ModerationChange,
+) -> Result<ModerationChangeOutcome> {
+    let account_id = match &change.mastodon_id {
+        Some(id) => id.clone(),
+        None => match mastodon::find_account(mastodon_user, &change.mastodon_acct).await? {
+            Some(account) => account.id,
+            None => return Ok(ModerationChangeOutcome::NotBridged),
+        },
+    };
+
+    match change.action {
+        ModerationAction::Block => {
+            if mastodon::get_relationship(mastodon_user, &account_id)
+                .await?
+                .blocking
+            {
+                return Ok(ModerationChangeOutcome::AlreadyDone);
+            }
+            mastodon::block_account(mastodon_user, &account_id).await?;
+        }
+        ModerationAction::Mute => {
+            if mastodon::get_relationship(mastodon_user, &account_id)
+                .await?
+                .muting
+            {
+                return Ok(ModerationChangeOutcome::AlreadyDone);
+            }
+            mastodon::mute_account(mastodon_user, &account_id).await?;
+        }
+        ModerationAction::Unblock => mastodon::unblock_account(mastodon_user, &account_id).await?,
+        ModerationAction::Unmute => mastodon::unmute_account(mastodon_user, &account_id).await?,
+    }
+
+    Ok(ModerationChangeOutcome::Applied {
+        mastodon_id: account_id,
+    })
+}
+
+pub fn history_command(
+    config_path: &Path,
+    profile: Option<&str>,
+    account: Option<&str>,
+    at: Option<&str>,
+) -> Result<()> {
+    let config = Config::from_file(config_path, profile)?;
+    let state = State::for_config(&config)?;
+
+    let Some(account) = account else {
+        let runs = &state.data().runs;
+        if runs.is_empty() {
+            println!("{}", "No runs recorded yet.".yellow());
+            return Ok(());
+        }
+
+        println!("Recent runs:");
+        for run in runs.iter().rev().take(20) {
+            let mut line = format!(
+                "  #{} {} {} - {} account(s) seen",
+                run.id,
+                format_timestamp(run.started_at).dimmed(),
+                run.command.blue(),
+                run.accounts_seen
+            );
+            if run.followed > 0 {
+                line += &format!(", {} followed", run.followed.green());
+            }
+            if run.follow_failures > 0 {
+                line += &format!(", {} failed", run.follow_failures.red());
+            }
+            if run.unfollowed > 0 {
+                line += &format!(", {} unfollowed", run.unfollowed.yellow());
+            }
+            println!("{line}");
+        }
+        return Ok(());
+    };
+
+    let Some((did, history)) = state.find_account(account) else {
+        bail!("No history recorded for account '{account}'");
+    };
+
+    println!("{} ({})", history.handle.as_str().blue(), did.dimmed());
+    println!(
+        "Mastodon account: {}",
+        format!("@{}", history.mastodon_acct).blue()
+    );
+
+    if let Some(at) = at {
+        let at = parse_point_in_time(at)?;
+        match history.status_at(at) {
+            Some(change) => println!(
+                "Status at {}: {} (since {})",
+                format_timestamp(at),
+                change.status.to_string().yellow(),
+                format_timestamp(change.at).dimmed()
+            ),
+            None => println!(
+                "Status at {}: {}",
+                format_timestamp(at),
+                "unknown (not seen yet)".dimmed()
+            ),
+        }
+        return Ok(());
+    }
+
+    println!();
+    println!("Status history:");
+    for change in &history.statuses {
+        println!(
+            "  {} {} {}",
+            format_timestamp(change.at).dimmed(),
+            change.status.to_string().yellow(),
+            format!("(run #{})", change.run).dimmed()
+        );
+    }
+
+    if !history.follows.is_empty() {
+        println!();
+        println!("Follow actions:");
+        for action in &history.follows {
+            let outcome = match &action.outcome {
+                FollowOutcome::Followed => "followed".green().to_string(),
+                FollowOutcome::Failed { error } => format!("failed: {error}").red().to_string(),
+                FollowOutcome::Unfollowed => "unfollowed".yellow().to_string(),
+            };
+            println!(
+                "  {} {} {}",
+                format_timestamp(action.at).dimmed(),
+                format!("@{}", action.mastodon_acct).blue(),
+                outcome
+            );
+        }
     }
 
     Ok(())
 }
 
-pub fn forget_command(config_path: &Path) -> Result<()> {
-    let mut config = Config::from_file(config_path)?;
+pub async fn forget_command(config_path: &Path, profile: Option<&str>) -> Result<()> {
+    let mut config = Config::from_file(config_path, profile)?;
 
-    let credential_builder = keyring::default::default_credential_builder();
+    let credential_builder = credentials::credential_builder(&config)?;
 
     // Get current values before clearing
     let bluesky_username = config.bluesky_username().map(ToString::to_string);
-    let mastodon_server = config.mastodon_server().map(ToString::to_string);
+    let mastodon_server = config.mastodon_server().map(mastodon::server_url);
+
+    // Revoke the Mastodon access token before deleting it, the application registration is kept for the next login
+    // Failures don't prevent forgetting the credentials, e.g. when the credential store can't be unlocked
+    if let Some(server) = &mastodon_server
+        && let Ok(credential) =
+            credentials::get_mastodon_access_token(&credential_builder, config.profile(), server)
+        && let Ok(token) = credential.get_password()
+        && let Ok(client_credential) =
+            credentials::get_mastodon_client(&credential_builder, config.profile(), server)
+    {
+        match mastodon::revoke_token(&client_credential, server, token).await {
+            Ok(()) => info!("Revoked Mastodon access token on '{server}'"),
+            Err(e) => eprintln!("Failed to revoke Mastodon access token on '{server}': {e}"),
+        }
+    }
 
     // Delete credentials from keyring
     credentials::delete_credentials(
         &credential_builder,
+        config.profile(),
         mastodon_server.as_deref(),
         bluesky_username.as_deref(),
     );
@@ -124,10 +1997,32 @@ pub fn config_command() -> Result<()> {
     Ok(())
 }
 
-pub fn ignores_list_command(config_path: &Path) -> Result<()> {
-    let mut config = Config::from_file(config_path)?;
+pub fn profiles_add_command(config_path: &Path, name: &str) -> Result<()> {
+    Config::create_profile(config_path, name)?;
+    println!(
+        "{} Added profile '{}', use it with `--profile {}`",
+        "✓".green(),
+        name,
+        name
+    );
+    Ok(())
+}
+
+pub fn ignores_list_command(config_path: &Path, profile: Option<&str>) -> Result<()> {
+    let mut config = Config::from_file(config_path, profile)?;
 
-    let ignored_accounts = config.ignored_accounts().clone();
+    // Accounts ignored by DID are listed by handle, followed by the ones that couldn't be resolved to a DID
+    let ignored_dids: Vec<(String, String)> = config
+        .ignored_dids()
+        .iter()
+        .map(|(did, handle)| (did.clone(), handle.clone()))
+        .collect();
+    let ignored_handles = config.ignored_accounts().clone();
+    let ignored_accounts: Vec<String> = ignored_dids
+        .iter()
+        .map(|(did, handle)| format!("{handle} {}", format!("({did})").dimmed()))
+        .chain(ignored_handles.iter().cloned())
+        .collect();
 
     if ignored_accounts.is_empty() {
         println!("{}", "No ignored accounts configured.".yellow());
@@ -135,6 +2030,12 @@ pub fn ignores_list_command(config_path: &Path) -> Result<()> {
     }
 
     println!("Current ignored accounts:");
+    if !interactive::is_interactive() {
+        for account in &ignored_accounts {
+            println!("  {account}");
+        }
+        return Ok(());
+    }
     println!(
         "{}",
         "Select accounts to remove from ignore list (Space to select, Enter to confirm):".dimmed()
@@ -155,10 +2056,18 @@ pub fn ignores_list_command(config_path: &Path) -> Result<()> {
         .iter()
         .map(|&idx| ignored_accounts[idx].clone())
         .collect();
+    let (dids_to_remove, handles_to_remove): (Vec<usize>, Vec<usize>) = selections
+        .iter()
+        .partition(|&&idx| idx < ignored_dids.len());
 
     config.mutate(|mut data| {
-        data.ignored_accounts
-            .retain(|account| !accounts_to_remove.contains(account));
+        for &idx in &dids_to_remove {
+            data.ignored_dids.remove(&ignored_dids[idx].0);
+        }
+        for &idx in &handles_to_remove {
+            let handle = &ignored_handles[idx - ignored_dids.len()];
+            data.ignored_accounts.retain(|account| account != handle);
+        }
         data
     })?;
 
@@ -175,13 +2084,23 @@ pub fn ignores_list_command(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-pub async fn ignores_add_command(account: Option<String>) -> Result<()> {
+pub async fn ignores_add_command(account: Option<String>, profile: Option<&str>) -> Result<()> {
     let config_path = default_config_path()?;
-    let mut config = Config::from_file(&config_path)?;
+    let mut config = Config::from_file(&config_path, profile)?;
 
     if let Some(account_handle) = account {
-        // Direct add mode
-        if config.ignored_accounts().contains(&account_handle) {
+        // Direct add mode, the account is ignored by DID so that it stays ignored if its handle changes. The handle is
+        // resolved without logging in to Bluesky.
+        let account_handle = account_handle.trim_start_matches('@').to_string();
+        let did = match bluesky::resolve_handle_without_login(&config, &account_handle).await {
+            Ok(did) => Some(did),
+            Err(e) => {
+                info!("Failed to resolve '{account_handle}', ignoring it by handle: {e}");
+                None
+            }
+        };
+
+        if config.is_ignored(did.as_ref().map(Did::as_str), &account_handle) {
             println!(
                 "{} Account '{}' is already in the ignore list",
                 "ℹ".blue(),
@@ -191,29 +2110,45 @@ pub async fn ignores_add_command(account: Option<String>) -> Result<()> {
         }
 
         config.mutate(|mut data| {
-            data.ignored_accounts.push(account_handle.clone());
+            match &did {
+                Some(did) => {
+                    data.ignored_dids
+                        .insert(did.as_str().to_string(), account_handle.clone());
+                }
+                None => data.ignored_accounts.push(account_handle.clone()),
+            }
             data
         })?;
 
         println!("{} Added '{}' to ignore list", "✓".green(), account_handle);
     } else {
         // Interactive mode - query followers and let user select
-        let credential_builder = keyring::default::default_credential_builder();
+        interactive::require_selection("pass the account to ignore")?;
+        let mut state = State::for_config(&config)?;
+        let credential_builder = credentials::credential_builder(&config)?;
 
         let mastodon_user = mastodon::authenticate(&credential_builder, &mut config).await?;
         let bluesky = bluesky::authenticate(&credential_builder, &mut config).await?;
 
         println!("Fetching followers...");
-        let statuses =
-            get_follower_statuses(&mastodon_user, &bluesky, config.ignored_accounts(), false)
-                .await?;
+        let statuses = get_follower_statuses(
+            &mastodon_user,
+            &bluesky,
+            &config,
+            &mut state,
+            &AccountSource::Graph(config.follower_source()),
+            false,
+        )
+        .await?;
+        state.save()?;
 
         // Get all accounts that could be followed (not already ignored, not already followed)
-        let available_accounts: Vec<String> = statuses
+        let available: Vec<_> = statuses
             .iter()
             .filter(|s| matches!(s.status, FollowerStatus::ReadyToFollow))
-            .map(|s| s.handle.to_string())
             .collect();
+        let available_accounts: Vec<String> =
+            available.iter().map(|s| s.handle.to_string()).collect();
 
         if available_accounts.is_empty() {
             println!("{}", "No accounts available to ignore.".yellow());
@@ -240,10 +2175,12 @@ pub async fn ignores_add_command(account: Option<String>) -> Result<()> {
             .collect();
 
         config.mutate(|mut data| {
-            for account in &accounts_to_add {
-                if !data.ignored_accounts.contains(account) {
-                    data.ignored_accounts.push(account.clone());
-                }
+            for &idx in &selections {
+                let follower = available[idx];
+                data.ignored_dids.insert(
+                    follower.did.as_str().to_string(),
+                    follower.handle.to_string(),
+                );
             }
             data
         })?;
@@ -1,40 +1,434 @@
-use color_eyre::{Result, eyre::eyre};
+use crate::follower_status::FollowerSource;
+use color_eyre::{
+    Result,
+    eyre::{bail, eyre},
+};
 use directories::ProjectDirs;
+use jiff::SignedDuration;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs, io,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ConfigData {
-    pub bluesky_username: Option<String>,
+    /// Version of the settings of the default profile, see [`CONFIG_VERSION`]
     #[serde(default)]
+    pub version: u32,
+    pub bluesky_username: Option<String>,
+    /// Ignored accounts by handle, moved to `ignored_dids` once their DID is resolved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub ignored_accounts: Vec<String>,
+    /// Ignored accounts by DID, with the handle they had when they were ignored
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ignored_dids: BTreeMap<String, String>,
+    pub mastodon_server: Option<String>,
+    /// Where credentials are stored
+    pub credential_store: Option<CredentialStore>,
+    /// Command printing a credential, for the `command` credential store
+    pub password_command: Option<String>,
+    /// Encrypted file holding the credentials, for the `file` credential store
+    pub credentials_file: Option<PathBuf>,
+    /// How to log in to Bluesky
+    pub bluesky_auth: Option<BlueskyAuth>,
+    /// Authorization server (or PDS) to start the Bluesky OAuth flow from, instead of the PDS of the account
+    pub bluesky_oauth_server: Option<String>,
+    /// Port of the loopback listener receiving the Bluesky OAuth redirect
+    pub bluesky_oauth_callback_port: Option<u16>,
+    /// Port of the loopback listener receiving the Mastodon OAuth redirect
+    pub mastodon_oauth_callback_port: Option<u16>,
+    /// PLC directory used to resolve did:plc identities
+    pub plc_directory_url: Option<String>,
+    /// AppView the PDS proxies `app.bsky` queries to, as a DID and service id (e.g. `did:web:api.bsky.app#bsky_appview`)
+    pub bluesky_appview: Option<String>,
+    /// Which Bluesky accounts are considered for following on Mastodon
+    pub follower_source: Option<FollowerSource>,
+    /// Title of a Mastodon list to which every account followed by `sync` is added
+    pub mastodon_list: Option<String>,
+    /// Maximum age of a plan created by `sync --plan` that `apply` will accept
+    pub plan_max_age_hours: Option<u64>,
+    /// Maximum number of WebFinger lookups running at the same time
+    pub webfinger_concurrency: Option<usize>,
+    /// Timeout of a single WebFinger lookup
+    pub webfinger_timeout_secs: Option<u64>,
+    /// Time between two syncs in `watch` mode
+    pub watch_interval_minutes: Option<u64>,
+    /// Maximum random delay added to the interval between two syncs in `watch` mode
+    pub watch_jitter_minutes: Option<u64>,
+    /// Options used when following accounts on Mastodon, both by `sync` and in the `csv` export
+    pub follow_options: Option<FollowOptionsData>,
+    /// Per-account follow options, keyed by Bluesky handle or DID, overriding `follow_options`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub follow_overrides: BTreeMap<String, FollowOptionsData>,
+    /// Named profiles, each with its own accounts and ignore list, selected with `--profile`. The accounts at the top
+    /// level of the configuration are the default profile.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileData>,
+}
+
+/// The settings specific to a profile: a Bluesky account, a Mastodon account and an ignore list
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProfileData {
+    /// Version of the settings of the profile, see [`CONFIG_VERSION`]
+    #[serde(default)]
+    pub version: u32,
+    pub bluesky_username: Option<String>,
     pub mastodon_server: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored_accounts: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ignored_dids: BTreeMap<String, String>,
+}
+
+impl ConfigData {
+    /// The profile settings stored at the top level of the configuration
+    fn profile_data(&self) -> ProfileData {
+        ProfileData {
+            version: self.version,
+            bluesky_username: self.bluesky_username.clone(),
+            mastodon_server: self.mastodon_server.clone(),
+            ignored_accounts: self.ignored_accounts.clone(),
+            ignored_dids: self.ignored_dids.clone(),
+        }
+    }
+
+    /// Replace the profile settings stored at the top level of the configuration
+    fn with_profile_data(self, profile: ProfileData) -> Self {
+        Self {
+            version: profile.version,
+            bluesky_username: profile.bluesky_username,
+            mastodon_server: profile.mastodon_server,
+            ignored_accounts: profile.ignored_accounts,
+            ignored_dids: profile.ignored_dids,
+            ..self
+        }
+    }
+}
+
+/// Where credentials are stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialStore {
+    /// The keyring of the system (Keychain, Credential Manager or Secret Service)
+    #[default]
+    Keyring,
+    /// Environment variables, read-only
+    Env,
+    /// The output of `password_command`, read-only
+    Command,
+    /// A local file encrypted with a passphrase
+    File,
+}
+
+/// How to log in to Bluesky
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlueskyAuth {
+    /// Authorize in the browser with OAuth, falling back to an app password if it fails
+    #[default]
+    #[serde(rename = "oauth")]
+    OAuth,
+    /// Log in with an app password
+    AppPassword,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FollowOptionsData {
+    /// Show boosts from the account in the home timeline
+    pub reblogs: Option<bool>,
+    /// Get a notification when the account posts
+    pub notify: Option<bool>,
+    /// Only show posts in these languages (ISO 639-1 codes), all languages if empty
+    pub languages: Option<Vec<String>>,
+}
+
+/// Options applied when following an account on Mastodon
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowOptions {
+    pub reblogs: bool,
+    pub notify: bool,
+    pub languages: Vec<String>,
+}
+
+impl Default for FollowOptions {
+    fn default() -> Self {
+        // Same defaults as Mastodon
+        Self {
+            reblogs: true,
+            notify: false,
+            languages: Vec::new(),
+        }
+    }
+}
+
+impl FollowOptions {
+    fn apply(mut self, data: &FollowOptionsData) -> Self {
+        if let Some(reblogs) = data.reblogs {
+            self.reblogs = reblogs;
+        }
+        if let Some(notify) = data.notify {
+            self.notify = notify;
+        }
+        if let Some(languages) = &data.languages {
+            self.languages = languages.clone();
+        }
+        self
+    }
+}
+
+/// Version of the profile settings, bumped when a migration is needed. Version 1 moved the ignores stored by handle
+/// to the ignores stored by DID.
+pub const CONFIG_VERSION: u32 = 1;
+
+const DEFAULT_PLAN_MAX_AGE_HOURS: u64 = 24;
+const DEFAULT_PLC_DIRECTORY_URL: &str = "https://plc.directory";
+const DEFAULT_BLUESKY_OAUTH_CALLBACK_PORT: u16 = 8427;
+const DEFAULT_MASTODON_OAUTH_CALLBACK_PORT: u16 = 8428;
+const DEFAULT_WEBFINGER_CONCURRENCY: usize = 16;
+const DEFAULT_WEBFINGER_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_WATCH_INTERVAL_MINUTES: u64 = 60;
+const DEFAULT_WATCH_JITTER_MINUTES: u64 = 5;
+/// Longest interval or jitter of `watch`, the time of the next sync must stay representable
+const MAX_WATCH_MINUTES: u64 = 366 * 24 * 60;
+
+/// Convert a delay of `watch` given in minutes, `setting` naming where it comes from in the error
+pub fn watch_delay(setting: &str, minutes: u64, min_minutes: u64) -> Result<Duration> {
+    match minutes.checked_mul(60) {
+        Some(secs) if (min_minutes..=MAX_WATCH_MINUTES).contains(&minutes) => {
+            Ok(Duration::from_secs(secs))
+        }
+        _ => bail!(
+            "`{setting}` must be between {min_minutes} and {MAX_WATCH_MINUTES} minutes ({minutes})"
+        ),
+    }
+}
+
+/// Profile names end up in file names (e.g. `config.work.state.json`) and keyring entries, so they are restricted to
+/// ASCII letters, digits, `_` and `-`
+fn validate_profile_name(profile: &str) -> Result<()> {
+    let valid = !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        bail!(
+            "Invalid profile name '{profile}', only ASCII letters, digits, `_` and `-` are allowed"
+        );
+    }
+    Ok(())
 }
 
 pub struct Config {
+    /// The configuration as seen from the selected profile, with the settings of the profile at the top level
     data: ConfigData,
+    /// The configuration as stored in the file
+    file_data: ConfigData,
+    profile: Option<String>,
     path: PathBuf,
 }
 
 impl Config {
+    /// The selected profile, `None` for the default profile
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// All the profiles of the configuration, starting with the default profile if it has any account configured
+    pub fn profiles(&self) -> Vec<Option<String>> {
+        let has_default =
+            self.file_data.bluesky_username.is_some() || self.file_data.mastodon_server.is_some();
+        has_default
+            .then_some(None)
+            .into_iter()
+            .chain(self.file_data.profiles.keys().cloned().map(Some))
+            .collect()
+    }
+
+    /// Path of a file stored next to the configuration file, namespaced by profile (e.g. `config.work.state.json`)
+    pub fn sibling_path(&self, extension: &str) -> PathBuf {
+        match &self.profile {
+            Some(profile) => self.path.with_extension(format!("{profile}.{extension}")),
+            None => self.path.with_extension(extension),
+        }
+    }
+
     pub fn mastodon_server(&self) -> Option<&str> {
         self.data.mastodon_server.as_deref()
     }
 
+    pub fn mastodon_list(&self) -> Option<&str> {
+        self.data.mastodon_list.as_deref()
+    }
+
     pub fn bluesky_username(&self) -> Option<&str> {
         self.data.bluesky_username.as_deref()
     }
 
+    pub fn credential_store(&self) -> CredentialStore {
+        self.data.credential_store.unwrap_or_default()
+    }
+
+    pub fn password_command(&self) -> Option<&str> {
+        self.data.password_command.as_deref()
+    }
+
+    /// The encrypted credentials file, next to the configuration file by default. It's shared by all the profiles.
+    pub fn credentials_file(&self) -> PathBuf {
+        self.data
+            .credentials_file
+            .clone()
+            .unwrap_or_else(|| self.path.with_extension("credentials"))
+    }
+
+    /// How to log in to Bluesky. Read-only credential stores can't save the OAuth session and its refreshed tokens, so
+    /// they default to app passwords and can't be used with OAuth.
+    pub fn bluesky_auth(&self) -> Result<BlueskyAuth> {
+        let read_only = matches!(
+            self.credential_store(),
+            CredentialStore::Env | CredentialStore::Command
+        );
+        match self.data.bluesky_auth {
+            Some(BlueskyAuth::OAuth) if read_only => bail!(
+                "`bluesky_auth = \"oauth\"` can't be used with the read-only `env` and `command` credential stores as the session can't be saved, use `bluesky_auth = \"app_password\"`"
+            ),
+            Some(auth) => Ok(auth),
+            None if read_only => Ok(BlueskyAuth::AppPassword),
+            None => Ok(BlueskyAuth::default()),
+        }
+    }
+
+    pub fn bluesky_oauth_server(&self) -> Option<&str> {
+        self.data.bluesky_oauth_server.as_deref()
+    }
+
+    pub fn bluesky_oauth_callback_port(&self) -> u16 {
+        self.data
+            .bluesky_oauth_callback_port
+            .unwrap_or(DEFAULT_BLUESKY_OAUTH_CALLBACK_PORT)
+    }
+
+    pub fn mastodon_oauth_callback_port(&self) -> u16 {
+        self.data
+            .mastodon_oauth_callback_port
+            .unwrap_or(DEFAULT_MASTODON_OAUTH_CALLBACK_PORT)
+    }
+
+    pub fn plc_directory_url(&self) -> &str {
+        self.data
+            .plc_directory_url
+            .as_deref()
+            .unwrap_or(DEFAULT_PLC_DIRECTORY_URL)
+    }
+
+    /// The AppView configured for `app.bsky` queries, `None` to use the default AppView of the PDS
+    pub fn bluesky_appview(&self) -> Option<&str> {
+        self.data.bluesky_appview.as_deref()
+    }
+
+    /// Version of the settings of the selected profile, older than [`CONFIG_VERSION`] until they are migrated
+    pub fn version(&self) -> u32 {
+        self.data.version
+    }
+
+    /// Ignored accounts that couldn't be resolved to a DID yet
     pub fn ignored_accounts(&self) -> &Vec<String> {
         &self.data.ignored_accounts
     }
 
-    /// Load the configuration from a file
-    pub fn from_file(path: &Path) -> Result<Self> {
-        let data = match fs::read_to_string(path) {
+    pub fn ignored_dids(&self) -> &BTreeMap<String, String> {
+        &self.data.ignored_dids
+    }
+
+    /// Whether an account is ignored, either by DID or by handle for the ignores that weren't resolved to a DID
+    pub fn is_ignored(&self, did: Option<&str>, handle: &str) -> bool {
+        let handle = handle.trim_start_matches('@');
+        did.is_some_and(|did| self.data.ignored_dids.contains_key(did))
+            || self
+                .data
+                .ignored_accounts
+                .iter()
+                .any(|ignored| ignored.trim_start_matches('@').eq_ignore_ascii_case(handle))
+    }
+
+    pub fn follower_source(&self) -> FollowerSource {
+        self.data.follower_source.unwrap_or_default()
+    }
+
+    pub fn plan_max_age(&self) -> Result<SignedDuration> {
+        let hours = self
+            .data
+            .plan_max_age_hours
+            .unwrap_or(DEFAULT_PLAN_MAX_AGE_HOURS);
+        match i64::try_from(hours) {
+            // Larger values don't fit in a duration
+            Ok(hours) if hours <= i64::MAX / 3600 => Ok(SignedDuration::from_hours(hours)),
+            _ => bail!("`plan_max_age_hours` is too large ({hours})"),
+        }
+    }
+
+    pub fn webfinger_concurrency(&self) -> usize {
+        self.data
+            .webfinger_concurrency
+            .unwrap_or(DEFAULT_WEBFINGER_CONCURRENCY)
+            .max(1)
+    }
+
+    pub fn webfinger_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.data
+                .webfinger_timeout_secs
+                .unwrap_or(DEFAULT_WEBFINGER_TIMEOUT_SECS),
+        )
+    }
+
+    /// At least a minute, so that `watch` doesn't sync in a loop against both APIs
+    pub fn watch_interval(&self) -> Result<Duration> {
+        let minutes = self
+            .data
+            .watch_interval_minutes
+            .unwrap_or(DEFAULT_WATCH_INTERVAL_MINUTES);
+        watch_delay("watch_interval_minutes", minutes, 1)
+    }
+
+    pub fn watch_jitter(&self) -> Result<Duration> {
+        let minutes = self
+            .data
+            .watch_jitter_minutes
+            .unwrap_or(DEFAULT_WATCH_JITTER_MINUTES);
+        watch_delay("watch_jitter_minutes", minutes, 0)
+    }
+
+    /// The follow options for an account: the configured defaults, overridden by any entry matching its handle or
+    /// DID. An entry for the DID takes precedence over one for the handle.
+    pub fn follow_options_for(&self, handle: &str, did: &str) -> FollowOptions {
+        let mut options = FollowOptions::default();
+        if let Some(defaults) = &self.data.follow_options {
+            options = options.apply(defaults);
+        }
+
+        let find_override = |key: &str| {
+            self.data
+                .follow_overrides
+                .iter()
+                .find(|(k, _)| k.trim_start_matches('@').eq_ignore_ascii_case(key))
+                .map(|(_, data)| data)
+        };
+        if let Some(data) = find_override(handle) {
+            options = options.apply(data);
+        }
+        if let Some(data) = find_override(did) {
+            options = options.apply(data);
+        }
+
+        options
+    }
+
+    /// Load the configuration from a file, as seen from a profile (`None` for the default profile)
+    pub fn from_file(path: &Path, profile: Option<&str>) -> Result<Self> {
+        let file_data = match fs::read_to_string(path) {
             Ok(contents) => {
                 let data: ConfigData = toml::from_str(&contents)?;
                 data
@@ -44,17 +438,67 @@ impl Config {
                 return Err(e.into());
             }
         };
+
+        // Profiles are created explicitly, so that a typo in `--profile` doesn't start a new profile from scratch
+        let data = match profile {
+            Some(profile) => {
+                validate_profile_name(profile)?;
+                let Some(profile_data) = file_data.profiles.get(profile).cloned() else {
+                    bail!(
+                        "Unknown profile '{profile}', create it with `bridgy_followers profiles add {profile}`"
+                    );
+                };
+                file_data.clone().with_profile_data(profile_data)
+            }
+            None => file_data.clone(),
+        };
+
         Ok(Config {
             data,
+            file_data,
+            profile: profile.map(ToString::to_string),
             path: path.to_path_buf(),
         })
     }
 
-    /// Save a new configuration to the configuration file
+    /// Add an empty profile to the configuration file
+    pub fn create_profile(path: &Path, profile: &str) -> Result<()> {
+        validate_profile_name(profile)?;
+        let mut config = Self::from_file(path, None)?;
+        if config.file_data.profiles.contains_key(profile) {
+            bail!("Profile '{profile}' already exists");
+        }
+        config.mutate(|mut data| {
+            data.profiles.insert(
+                profile.to_string(),
+                ProfileData {
+                    version: CONFIG_VERSION,
+                    ..ProfileData::default()
+                },
+            );
+            data
+        })
+    }
+
+    /// Save a new configuration to the configuration file, changes to the profile settings are saved to the selected
+    /// profile
     pub fn mutate(&mut self, mutation: impl Fn(ConfigData) -> ConfigData) -> Result<()> {
         let new_data = mutation(self.data.clone());
-        let toml_string = toml::to_string_pretty(&new_data)?;
+        let new_file_data = match &self.profile {
+            Some(profile) => {
+                let mut file_data = new_data
+                    .clone()
+                    .with_profile_data(self.file_data.profile_data());
+                file_data
+                    .profiles
+                    .insert(profile.clone(), new_data.profile_data());
+                file_data
+            }
+            None => new_data.clone(),
+        };
+        let toml_string = toml::to_string_pretty(&new_file_data)?;
         self.data = new_data;
+        self.file_data = new_file_data;
 
         // Ensure the parent directory exists
         if let Some(parent) = self.path.parent() {
@@ -0,0 +1,125 @@
+use keyring::{
+    Credential,
+    credential::{CredentialApi, CredentialBuilderApi},
+};
+use std::{any::Any, process::Command};
+use tracing::debug;
+
+/// Reads credentials from the output of a command, e.g. `pass show "bridgy_followers/$BRIDGY_FOLLOWERS_USER"`. The
+/// command is run by the shell with the service and user of the credential in `BRIDGY_FOLLOWERS_SERVICE` and
+/// `BRIDGY_FOLLOWERS_USER`, a command that fails is considered to have no entry for them. Like the tools it wraps this
+/// store is read-only.
+#[derive(Debug)]
+pub struct CommandCredentialBuilder {
+    command: String,
+}
+
+impl CommandCredentialBuilder {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl CredentialBuilderApi for CommandCredentialBuilder {
+    fn build(
+        &self,
+        _target: Option<&str>,
+        service: &str,
+        user: &str,
+    ) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(CommandCredential {
+            command: self.command.clone(),
+            service: service.to_string(),
+            user: user.to_string(),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Debug)]
+struct CommandCredential {
+    command: String,
+    service: String,
+    user: String,
+}
+
+impl CommandCredential {
+    fn read_only(&self) -> keyring::Error {
+        keyring::Error::NotSupportedByStore(format!(
+            "password_command is read-only, store the secret for '{}' in it first",
+            self.user
+        ))
+    }
+
+    fn shell(&self) -> Command {
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        command
+            .arg(&self.command)
+            .env("BRIDGY_FOLLOWERS_SERVICE", &self.service)
+            .env("BRIDGY_FOLLOWERS_USER", &self.user);
+        command
+    }
+}
+
+impl CredentialApi for CommandCredential {
+    fn set_password(&self, _password: &str) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn set_secret(&self, _secret: &[u8]) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn get_password(&self) -> keyring::Result<String> {
+        let secret = self.get_secret()?;
+        String::from_utf8(secret).map_err(|e| keyring::Error::BadEncoding(e.into_bytes()))
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        let output = self
+            .shell()
+            .output()
+            .map_err(|e| keyring::Error::PlatformFailure(Box::new(e)))?;
+
+        if !output.status.success() {
+            debug!(
+                "password_command failed for '{}' ({}): {}",
+                self.user,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Err(keyring::Error::NoEntry);
+        }
+
+        // Tools print the secret followed by a newline
+        let mut secret = output.stdout;
+        while secret.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+            secret.pop();
+        }
+        if secret.is_empty() {
+            return Err(keyring::Error::NoEntry);
+        }
+        Ok(secret)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
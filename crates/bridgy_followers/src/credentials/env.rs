@@ -0,0 +1,86 @@
+use keyring::{
+    Credential,
+    credential::{CredentialApi, CredentialBuilderApi},
+};
+use std::{any::Any, env};
+
+/// Reads credentials from environment variables, e.g. `BRIDGY_FOLLOWERS_BLUESKY_ALICE_BSKY_SOCIAL` for the Bluesky
+/// password of `alice.bsky.social`. Variables can't be set from here, so this store is read-only.
+#[derive(Debug)]
+pub struct EnvCredentialBuilder;
+
+impl CredentialBuilderApi for EnvCredentialBuilder {
+    fn build(
+        &self,
+        _target: Option<&str>,
+        service: &str,
+        user: &str,
+    ) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(EnvCredential {
+            variable: variable_name(service, user),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Upper case the service and user, replacing each run of other characters than letters and digits with `_`
+fn variable_name(service: &str, user: &str) -> String {
+    let mut name = String::new();
+    for c in format!("{service}_{user}").chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_uppercase());
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    name.trim_end_matches('_').to_string()
+}
+
+#[derive(Debug)]
+struct EnvCredential {
+    variable: String,
+}
+
+impl EnvCredential {
+    fn read_only(&self) -> keyring::Error {
+        keyring::Error::NotSupportedByStore(format!(
+            "environment variables are read-only, set {} instead",
+            self.variable
+        ))
+    }
+}
+
+impl CredentialApi for EnvCredential {
+    fn set_password(&self, _password: &str) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn set_secret(&self, _secret: &[u8]) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn get_password(&self) -> keyring::Result<String> {
+        match env::var(&self.variable) {
+            Ok(value) => Ok(value),
+            Err(env::VarError::NotPresent) => Err(keyring::Error::NoEntry),
+            Err(env::VarError::NotUnicode(value)) => {
+                Err(keyring::Error::BadEncoding(value.into_encoded_bytes()))
+            }
+        }
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        self.get_password().map(String::into_bytes)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{exit_code::AuthRequired, interactive, utils::replace_file};
+use argon2::Argon2;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce, aead::Aead};
+use dialoguer::{Password, theme::ColorfulTheme};
+use keyring::{
+    Credential,
+    credential::{CredentialApi, CredentialBuilderApi},
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// Environment variable holding the passphrase, it's prompted for when not set
+const PASSPHRASE_VARIABLE: &str = "BRIDGY_FOLLOWERS_PASSPHRASE";
+
+const FORMAT_VERSION: u32 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+/// The encrypted file, the secrets are a JSON object keyed by service and user
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+type Secrets = BTreeMap<String, String>;
+
+fn failure(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> keyring::Error {
+    keyring::Error::PlatformFailure(error.into())
+}
+
+/// Stores credentials in a local file encrypted with a passphrase, for machines without a keyring. The key is derived
+/// from the passphrase with Argon2id and the secrets are encrypted with XChaCha20-Poly1305.
+#[derive(Debug)]
+pub struct FileCredentialBuilder {
+    file: Arc<SecretsFile>,
+}
+
+impl FileCredentialBuilder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            file: Arc::new(SecretsFile {
+                path,
+                passphrase: OnceLock::new(),
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+}
+
+impl CredentialBuilderApi for FileCredentialBuilder {
+    fn build(
+        &self,
+        _target: Option<&str>,
+        service: &str,
+        user: &str,
+    ) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(FileCredential {
+            file: Arc::clone(&self.file),
+            key: format!("{service}/{user}"),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Debug)]
+struct SecretsFile {
+    path: PathBuf,
+    /// Asked once and shared by all the credentials
+    passphrase: OnceLock<String>,
+    /// Serializes the read-modify-write cycles of the credentials
+    lock: Mutex<()>,
+}
+
+impl SecretsFile {
+    fn passphrase(&self, creating: bool) -> keyring::Result<&str> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+
+        let passphrase = match env::var(PASSPHRASE_VARIABLE) {
+            Ok(passphrase) => passphrase,
+            Err(_) if interactive::is_interactive() => {
+                let prompt = Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Passphrase for {}", self.path.display()));
+                let prompt = if creating {
+                    prompt.with_confirmation("Confirm passphrase", "Passphrases don't match")
+                } else {
+                    prompt
+                };
+                prompt.interact().map_err(failure)?
+            }
+            // The source of the keyring error, so that the command exits with the authentication required code
+            Err(_) => {
+                return Err(keyring::Error::NoStorageAccess(Box::new(AuthRequired(
+                    format!(
+                        "set {PASSPHRASE_VARIABLE} to unlock {}",
+                        self.path.display()
+                    ),
+                ))));
+            }
+        };
+
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+
+    fn cipher(&self, salt: &[u8], creating: bool) -> keyring::Result<XChaCha20Poly1305> {
+        let passphrase = self.passphrase(creating)?;
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| failure(e.to_string()))?;
+        Ok(XChaCha20Poly1305::new(&key))
+    }
+
+    fn load(&self) -> keyring::Result<Secrets> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Secrets::new()),
+            Err(e) => return Err(failure(e)),
+        };
+
+        let file: EncryptedFile = serde_json::from_str(&content).map_err(failure)?;
+        if file.version != FORMAT_VERSION {
+            return Err(failure(format!(
+                "unsupported credentials file version {}",
+                file.version
+            )));
+        }
+        let salt = STANDARD.decode(file.salt).map_err(failure)?;
+        let nonce = STANDARD.decode(file.nonce).map_err(failure)?;
+        let ciphertext = STANDARD.decode(file.ciphertext).map_err(failure)?;
+        if nonce.len() != NONCE_LENGTH {
+            return Err(failure("invalid nonce in the credentials file"));
+        }
+
+        let plaintext = self
+            .cipher(&salt, false)?
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| {
+                keyring::Error::NoStorageAccess(
+                    format!("wrong passphrase for {}", self.path.display()).into(),
+                )
+            })?;
+        serde_json::from_slice(&plaintext).map_err(failure)
+    }
+
+    fn save(&self, secrets: &Secrets) -> keyring::Result<()> {
+        let creating = !self.path.exists();
+        let mut salt = [0; SALT_LENGTH];
+        let mut nonce = [0; NONCE_LENGTH];
+        rand::rng().fill(&mut salt);
+        rand::rng().fill(&mut nonce);
+
+        let plaintext = serde_json::to_vec(secrets).map_err(failure)?;
+        let ciphertext = self
+            .cipher(&salt, creating)?
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|e| failure(e.to_string()))?;
+        let file = EncryptedFile {
+            version: FORMAT_VERSION,
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        let content = serde_json::to_string_pretty(&file).map_err(failure)?;
+
+        // An interrupted write mustn't lose every secret
+        replace_file(&self.path, |temp_path| {
+            write_private(temp_path, content.as_bytes())
+        })
+        .map_err(failure)
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Secrets) -> bool) -> keyring::Result<bool> {
+        let _guard = self.lock.lock().map_err(|e| failure(e.to_string()))?;
+        let mut secrets = self.load()?;
+        let changed = update(&mut secrets);
+        if changed {
+            self.save(&secrets)?;
+        }
+        Ok(changed)
+    }
+}
+
+/// Write a file only readable by its owner. The permissions are set on the file itself too, as the mode given when
+/// opening it only applies to new files and is reduced by the umask.
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+#[derive(Debug)]
+struct FileCredential {
+    file: Arc<SecretsFile>,
+    key: String,
+}
+
+impl CredentialApi for FileCredential {
+    fn set_password(&self, password: &str) -> keyring::Result<()> {
+        self.file.update(|secrets| {
+            secrets.insert(self.key.clone(), password.to_string());
+            true
+        })?;
+        Ok(())
+    }
+
+    fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+        let password = String::from_utf8(secret.to_vec())
+            .map_err(|e| keyring::Error::BadEncoding(e.into_bytes()))?;
+        self.set_password(&password)
+    }
+
+    fn get_password(&self) -> keyring::Result<String> {
+        self.file
+            .load()?
+            .remove(&self.key)
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        self.get_password().map(String::into_bytes)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        if self
+            .file
+            .update(|secrets| secrets.remove(&self.key).is_some())?
+        {
+            Ok(())
+        } else {
+            Err(keyring::Error::NoEntry)
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,140 @@
+mod command;
+mod env;
+mod file;
+
+use crate::config::{Config, CredentialStore};
+use color_eyre::{Result, eyre::bail};
+use command::CommandCredentialBuilder;
+use env::EnvCredentialBuilder;
+use file::FileCredentialBuilder;
+use keyring::{Credential, CredentialBuilder};
+use tracing::{debug, info};
+
+/// Create the builder of the credential store selected in the configuration
+pub fn credential_builder(config: &Config) -> Result<Box<CredentialBuilder>> {
+    Ok(match config.credential_store() {
+        CredentialStore::Keyring => keyring::default::default_credential_builder(),
+        CredentialStore::Env => Box::new(EnvCredentialBuilder),
+        CredentialStore::Command => {
+            let Some(command) = config.password_command() else {
+                bail!("The command credential store needs `password_command` in the configuration");
+            };
+            Box::new(CommandCredentialBuilder::new(command))
+        }
+        CredentialStore::File => Box::new(FileCredentialBuilder::new(config.credentials_file())),
+    })
+}
+
+/// Keyring service of a profile, so that two profiles can use accounts on the same server. The default profile keeps
+/// the service used before profiles existed.
+fn service_name(profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("bridgy_followers.{profile}"),
+        None => "bridgy_followers".to_string(),
+    }
+}
+
+/// Get the Keyring credential for storing/retrieving the Bluesky password
+pub fn get_bluesky_password(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    user_name: &str,
+) -> keyring::Result<Box<Credential>> {
+    credential_builder.build(
+        None,
+        &service_name(profile),
+        &format!("bluesky_{user_name}"),
+    )
+}
+
+/// Get the Keyring credential for storing/retrieving the Bluesky OAuth session (DPoP key and tokens)
+pub fn get_bluesky_oauth_session(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    user_name: &str,
+) -> keyring::Result<Box<Credential>> {
+    credential_builder.build(
+        None,
+        &service_name(profile),
+        &format!("bluesky_oauth_{user_name}"),
+    )
+}
+
+/// Get the Keyring credential for storing/retrieving the Mastodon access token
+pub fn get_mastodon_access_token(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    server: &str,
+) -> keyring::Result<Box<Credential>> {
+    credential_builder.build(
+        None,
+        &service_name(profile),
+        &format!("mastodon_access_token_{server}"),
+    )
+}
+
+/// Get the Keyring credential for storing/retrieving the application registered on a Mastodon server
+pub fn get_mastodon_client(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    server: &str,
+) -> keyring::Result<Box<Credential>> {
+    credential_builder.build(
+        None,
+        &service_name(profile),
+        &format!("mastodon_client_{server}"),
+    )
+}
+
+/// Delete stored credentials from keyring
+pub fn delete_credentials(
+    credential_builder: &Box<CredentialBuilder>,
+    profile: Option<&str>,
+    mastodon_server: Option<&str>,
+    bluesky_username: Option<&str>,
+) {
+    // Delete Mastodon credential if server is known
+    if let Some(server) = mastodon_server
+        && let Ok(credential) = get_mastodon_access_token(credential_builder, profile, server)
+    {
+        match credential.delete_credential() {
+            Ok(()) => info!("Deleted Mastodon credentials for server '{server}'"),
+            Err(e) => {
+                if let keyring::Error::NoEntry = e {
+                    debug!("No Mastodon credentials found for server '{server}'");
+                }
+                eprintln!("Failed to delete Mastodon credentials for server '{server}': {e}");
+            }
+        }
+    }
+
+    // Delete Bluesky credential if username is known
+    if let Some(username) = bluesky_username
+        && let Ok(credential) = get_bluesky_password(credential_builder, profile, username)
+    {
+        match credential.delete_credential() {
+            Ok(()) => info!("Deleted Bluesky credentials for username '{username}'"),
+            Err(e) => {
+                if let keyring::Error::NoEntry = e {
+                    debug!("No Bluesky credentials found for username '{username}'");
+                }
+                eprintln!("Failed to delete Bluesky credentials for username '{username}': {e}");
+            }
+        }
+    }
+
+    // Delete the Bluesky OAuth session, only present when logged in with OAuth
+    if let Some(username) = bluesky_username
+        && let Ok(credential) = get_bluesky_oauth_session(credential_builder, profile, username)
+    {
+        match credential.delete_credential() {
+            Ok(()) => info!("Deleted Bluesky OAuth session for username '{username}'"),
+            Err(keyring::Error::NoEntry) => {
+                debug!("No Bluesky OAuth session found for username '{username}'")
+            }
+            Err(e) => {
+                eprintln!("Failed to delete Bluesky OAuth session for username '{username}': {e}")
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::interactive;
+use color_eyre::Report;
+use std::{fmt, process::ExitCode};
+
+/// Exit code when credentials are missing or no longer valid and logging in again needs the user
+pub const AUTH_REQUIRED: u8 = 3;
+
+/// Exit code when some of the changes of a command failed
+pub const PARTIAL_FAILURE: u8 = 4;
+
+/// Exit code when there was nothing to change, only with `--non-interactive`
+pub const NOTHING_TO_DO: u8 = 5;
+
+/// How a command ended when it didn't fail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Changes were made, or would have been made in a dry run
+    Done,
+    /// There was nothing to change
+    NothingToDo,
+    /// Some of the changes failed
+    PartialFailure,
+}
+
+impl Outcome {
+    /// The outcome of several runs: any failure wins, and there was nothing to do only if no run had anything to do
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Outcome::PartialFailure, _) | (_, Outcome::PartialFailure) => Outcome::PartialFailure,
+            (Outcome::NothingToDo, Outcome::NothingToDo) => Outcome::NothingToDo,
+            _ => Outcome::Done,
+        }
+    }
+
+    fn exit_code(self) -> ExitCode {
+        match self {
+            Outcome::Done => ExitCode::SUCCESS,
+            // Not when stdin alone enables non-interactive mode, so that the exit code doesn't depend on the terminal
+            Outcome::NothingToDo if interactive::non_interactive_flag() => {
+                ExitCode::from(NOTHING_TO_DO)
+            }
+            Outcome::NothingToDo => ExitCode::SUCCESS,
+            Outcome::PartialFailure => ExitCode::from(PARTIAL_FAILURE),
+        }
+    }
+}
+
+impl From<()> for Outcome {
+    fn from((): ()) -> Self {
+        Outcome::Done
+    }
+}
+
+/// Credentials are missing or no longer valid, and can't be renewed without the user
+#[derive(Debug)]
+pub struct AuthRequired(pub String);
+
+impl fmt::Display for AuthRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for AuthRequired {}
+
+/// Turn the outcome or the error of a command into an exit code, so that scripts can tell them apart
+pub fn from_result(result: Result<Outcome, Report>) -> Result<ExitCode, Report> {
+    match result {
+        Err(e) if e.chain().any(|cause| cause.is::<AuthRequired>()) => {
+            eprintln!("Error: {e:?}");
+            Ok(ExitCode::from(AUTH_REQUIRED))
+        }
+        result => result.map(Outcome::exit_code),
+    }
+}
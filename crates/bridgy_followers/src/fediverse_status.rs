@@ -0,0 +1,163 @@
+use crate::{
+    bluesky::{BlueskyAgent, get_follows, get_own_did, get_profiles},
+    config::Config,
+    follower_status::{FollowerStatus, NotBridgedReason},
+    mastodon,
+    utils::{mastodon_acct_to_bluesky_handle, mastodon_acct_to_bridged_bluesky_handle},
+};
+use atrium_api::types::string::{AtIdentifier, Did, Handle};
+use color_eyre::{Result, eyre::eyre};
+use megalodon::mastodon::Mastodon;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+use url::Url;
+
+/// Represents a fediverse account followed on Mastodon and its status as a bridged account on Bluesky
+#[derive(Debug, Clone)]
+pub struct BridgedFediverseAccount {
+    /// The account address on Mastodon (e.g. "user@mastodon.social")
+    pub mastodon_acct: String,
+    /// The handle of the bridged account on Bluesky (e.g. "user.mastodon.social.ap.brid.gy")
+    pub bluesky_handle: String,
+    /// The DID of the bridged account, only known if it exists on Bluesky
+    pub did: Option<Did>,
+    pub status: FollowerStatus,
+}
+
+impl BridgedFediverseAccount {
+    pub fn new(
+        mastodon_acct: String,
+        bluesky_handle: String,
+        did: Option<Did>,
+        status: FollowerStatus,
+    ) -> Self {
+        Self {
+            mastodon_acct,
+            bluesky_handle,
+            did,
+            status,
+        }
+    }
+}
+
+/// Compute the status of each account followed on Mastodon as a bridged account to follow on Bluesky.
+///
+/// This is the mirror of [`crate::follower_status::get_follower_statuses`].
+pub async fn get_fediverse_statuses(
+    mastodon_user: &Mastodon,
+    bluesky: &BlueskyAgent,
+    config: &Config,
+    quiet: bool,
+) -> Result<Vec<BridgedFediverseAccount>> {
+    let local_domain = config
+        .mastodon_server()
+        .and_then(|server| Url::parse(&mastodon::server_url(server)).ok())
+        .and_then(|url| url.host_str().map(ToString::to_string))
+        .ok_or_else(|| eyre!("Mastodon server is not configured"))?;
+
+    // Start the process with all the accounts followed on Mastodon, except those that are themselves bridged
+    // from Bluesky
+    let mastodon_following = mastodon::get_following_accounts(mastodon_user, quiet).await?;
+    let to_process = mastodon_following
+        .into_iter()
+        .filter(|account| mastodon_acct_to_bluesky_handle(&account.acct).is_none())
+        .map(|account| {
+            let acct = account.acct.to_lowercase();
+            let handle = mastodon_acct_to_bridged_bluesky_handle(&acct, &local_domain);
+            (acct, handle)
+        });
+
+    let mut result = Vec::<BridgedFediverseAccount>::new();
+
+    // ----------------------------------------------------------------------
+    // Pass 1: filter accounts ignored in the configuration, either by Mastodon address or Bluesky handle. Ignores by
+    // DID are checked in pass 3 once the DID is known.
+    let to_process: Vec<_> = to_process
+        .filter(|(acct, handle)| {
+            let ignored = config.is_ignored(None, acct) || config.is_ignored(None, handle);
+            if ignored {
+                info!("User '{acct}' in ignore list");
+                result.push(BridgedFediverseAccount::new(
+                    acct.clone(),
+                    handle.clone(),
+                    None,
+                    FollowerStatus::Ignored,
+                ));
+                return false;
+            }
+            true
+        })
+        .collect();
+
+    // ----------------------------------------------------------------------
+    // Pass 2: find the bridged profiles on Bluesky, accounts without one aren't bridged. Handles that aren't valid
+    // can't exist on Bluesky either.
+    let actors = to_process
+        .iter()
+        .filter_map(|(_, handle)| Handle::new(handle.clone()).ok())
+        .map(AtIdentifier::Handle);
+    let profiles: HashMap<String, Did> = get_profiles(bluesky, actors)
+        .await?
+        .into_iter()
+        .map(|profile| (profile.handle.as_str().to_lowercase(), profile.did.clone()))
+        .collect();
+
+    let to_process: Vec<_> = to_process
+        .into_iter()
+        .filter_map(|(acct, handle)| match profiles.get(&handle) {
+            Some(did) => Some((acct, handle, did.clone())),
+            None => {
+                info!("User '{acct}' has no bridged profile '{handle}' on Bluesky");
+                result.push(BridgedFediverseAccount::new(
+                    acct,
+                    handle,
+                    None,
+                    FollowerStatus::NotBridged(NotBridgedReason::NoAccountOnBluesky),
+                ));
+                None
+            }
+        })
+        .collect();
+
+    // ----------------------------------------------------------------------
+    // Pass 3: filter accounts ignored by DID or already followed on Bluesky
+    let own_did = get_own_did(bluesky).await?;
+    let bluesky_following: HashSet<Did> =
+        get_follows(bluesky, &own_did).await?.into_keys().collect();
+
+    for (acct, handle, did) in to_process {
+        if config.is_ignored(Some(did.as_str()), &handle) {
+            info!(did = did.as_str(), "User '{acct}' in ignore list");
+            result.push(BridgedFediverseAccount::new(
+                acct,
+                handle,
+                Some(did),
+                FollowerStatus::Ignored,
+            ));
+        } else if bluesky_following.contains(&did) {
+            info!(
+                did = did.as_str(),
+                "User '{acct}' already followed on Bluesky as {handle}"
+            );
+            result.push(BridgedFediverseAccount::new(
+                acct,
+                handle,
+                Some(did),
+                FollowerStatus::AlreadyFollowedOnBluesky,
+            ));
+        } else {
+            info!(
+                did = did.as_str(),
+                "User '{acct}' is bridged as {handle} and ready to follow"
+            );
+            result.push(BridgedFediverseAccount::new(
+                acct,
+                handle,
+                Some(did),
+                FollowerStatus::ReadyToFollow,
+            ));
+        }
+    }
+
+    Ok(result)
+}
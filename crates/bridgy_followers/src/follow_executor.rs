@@ -0,0 +1,369 @@
+use crate::{
+    config::{Config, FollowOptions},
+    exit_code::Outcome,
+    follower_status::BridgedFollower,
+    mastodon::{self, FollowedAccount, MastodonClient, RateLimit, RateLimited},
+    utils::{format_timestamp, write_atomically},
+};
+use color_eyre::{
+    Report, Result,
+    eyre::{WrapErr, eyre},
+    owo_colors::OwoColorize,
+};
+use jiff::Timestamp;
+use megalodon::error::Error as MegalodonError;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// Number of times a follow is retried after a transient failure
+const MAX_RETRIES: u32 = 3;
+
+/// Initial delay before retrying a failed follow, doubled on each attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay used when Mastodon rate limits us without telling us when the limit resets
+const DEFAULT_RATE_LIMITED_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Keep a few requests in reserve, a follow costs both a lookup and a follow request
+const RATE_LIMIT_RESERVE: u64 = 2;
+
+/// How a failed follow should be handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailureKind {
+    /// The server returned 429, wait for the rate limit to reset, after the delay it gave if any
+    RateLimited(Option<Duration>),
+    /// Network error or server error, retry after a delay
+    Transient,
+    /// Retrying won't help (e.g. the account doesn't exist)
+    Permanent,
+}
+
+fn classify_failure(error: &Report) -> FailureKind {
+    if let Some(rate_limited) = error.downcast_ref::<RateLimited>() {
+        return FailureKind::RateLimited(rate_limited.retry_after);
+    }
+
+    // Follows are done directly with reqwest rather than through megalodon
+    if let Some(request_error) = error.downcast_ref::<reqwest::Error>() {
+        return match request_error.status() {
+            Some(status) if status.as_u16() == 429 => FailureKind::RateLimited(None),
+            Some(status) if status.is_server_error() => FailureKind::Transient,
+            None if request_error.is_timeout() || request_error.is_connect() => {
+                FailureKind::Transient
+            }
+            _ => FailureKind::Permanent,
+        };
+    }
+
+    match error.downcast_ref::<MegalodonError>() {
+        Some(MegalodonError::OwnError(own_error)) => match own_error.status {
+            Some(429) => FailureKind::RateLimited(None),
+            Some(status) if status >= 500 => FailureKind::Transient,
+            _ => FailureKind::Permanent,
+        },
+        Some(MegalodonError::RequestError(request_error))
+            if request_error.is_timeout() || request_error.is_connect() =>
+        {
+            FailureKind::Transient
+        }
+        _ => FailureKind::Permanent,
+    }
+}
+
+/// Delay before retrying a failed follow, `rate_limit` being the one reported by the last successful response
+fn retry_delay(kind: FailureKind, attempt: u32, rate_limit: Option<RateLimit>) -> Duration {
+    match kind {
+        FailureKind::RateLimited(Some(retry_after)) => retry_after,
+        // Without a delay in the 429 response, fall back to the window of the last successful response
+        FailureKind::RateLimited(None) => rate_limit
+            .and_then(|rate_limit| {
+                Duration::try_from(Timestamp::now().duration_until(rate_limit.reset)).ok()
+            })
+            .filter(|wait| !wait.is_zero())
+            .unwrap_or(DEFAULT_RATE_LIMITED_DELAY),
+        FailureKind::Transient | FailureKind::Permanent => INITIAL_RETRY_DELAY * 2u32.pow(attempt),
+    }
+}
+
+/// Follows accounts on Mastodon while respecting the server rate limits and retrying transient failures
+pub struct FollowExecutor<'a> {
+    client: &'a MastodonClient,
+    rate_limit: Option<RateLimit>,
+}
+
+impl<'a> FollowExecutor<'a> {
+    pub fn new(client: &'a MastodonClient) -> Self {
+        Self {
+            client,
+            rate_limit: None,
+        }
+    }
+
+    /// Wait until the rate limit window resets if the last response said we are about to exhaust it
+    pub async fn wait_for_rate_limit(&mut self) {
+        let Some(rate_limit) = self.rate_limit else {
+            return;
+        };
+        if rate_limit.remaining > RATE_LIMIT_RESERVE {
+            return;
+        }
+
+        let wait = Timestamp::now().duration_until(rate_limit.reset);
+        if let Ok(wait) = Duration::try_from(wait)
+            && !wait.is_zero()
+        {
+            println!(
+                "{}",
+                format!(
+                    "Rate limit almost reached, waiting until {} before continuing...",
+                    format_timestamp(rate_limit.reset)
+                )
+                .yellow()
+            );
+            tokio::time::sleep(wait).await;
+        }
+        self.rate_limit = None;
+    }
+
+    /// Follow an account, retrying transient failures and waiting when rate limited
+    pub async fn follow(
+        &mut self,
+        account_handle: &str,
+        options: &FollowOptions,
+    ) -> Result<FollowedAccount> {
+        let mut account_id = None;
+        let mut attempt = 0;
+        loop {
+            match self
+                .lookup_and_follow(account_handle, options, &mut account_id)
+                .await
+            {
+                Ok(followed) => {
+                    if followed.rate_limit.is_some() {
+                        self.rate_limit = followed.rate_limit;
+                    }
+                    return Ok(followed);
+                }
+                Err(e) => {
+                    let kind = classify_failure(&e);
+                    if kind == FailureKind::Permanent || attempt >= MAX_RETRIES {
+                        return Err(e);
+                    }
+
+                    let delay = retry_delay(kind, attempt, self.rate_limit);
+                    attempt += 1;
+                    warn!(
+                        ?kind,
+                        attempt,
+                        "Failed to follow {account_handle}, retrying in {}s: {e}",
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    /// Follow an account, looking up its id first unless a previous attempt already did so that retries only repeat
+    /// the follow request
+    async fn lookup_and_follow(
+        &mut self,
+        account_handle: &str,
+        options: &FollowOptions,
+        account_id: &mut Option<String>,
+    ) -> Result<FollowedAccount> {
+        let id = match account_id.take() {
+            Some(id) => id,
+            None => {
+                let (id, rate_limit) =
+                    mastodon::lookup_account_id(self.client, account_handle).await?;
+                if rate_limit.is_some() {
+                    self.rate_limit = rate_limit;
+                }
+                id
+            }
+        };
+        let followed = mastodon::follow_account(self.client, account_handle, &id, options).await;
+        *account_id = Some(id);
+        followed
+    }
+}
+
+/// Outcome of following a batch of accounts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FollowSummary {
+    pub followed: usize,
+    pub failed: usize,
+}
+
+impl FollowSummary {
+    pub fn outcome(&self) -> Outcome {
+        if self.failed > 0 {
+            Outcome::PartialFailure
+        } else {
+            Outcome::Done
+        }
+    }
+}
+
+/// Progress of a sync, saved after each follow so that an interrupted sync can be resumed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FollowCheckpoint {
+    pub created_at: Timestamp,
+    /// The Mastodon server the follows are done on
+    pub mastodon_server: String,
+    pub followers: Vec<BridgedFollower>,
+    /// DIDs of the accounts that were already followed
+    #[serde(default)]
+    pub completed: BTreeSet<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl FollowCheckpoint {
+    pub fn new(path: PathBuf, mastodon_server: String, followers: Vec<BridgedFollower>) -> Self {
+        Self {
+            created_at: Timestamp::now(),
+            mastodon_server,
+            followers,
+            completed: BTreeSet::new(),
+            path,
+        }
+    }
+
+    /// Load a checkpoint, returning `None` if there is no interrupted sync
+    pub fn from_file(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let mut checkpoint: Self = serde_json::from_str(&contents)
+                    .wrap_err_with(|| format!("Failed to parse checkpoint {}", path.display()))?;
+                checkpoint.path = path.to_path_buf();
+                Ok(Some(checkpoint))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Load the checkpoint of an interrupted sync, failing if there is none
+    pub fn resume(path: &Path) -> Result<Self> {
+        Self::from_file(path)?.ok_or_else(|| eyre!("There is no interrupted sync to resume"))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(&self.path, json.as_bytes())
+            .wrap_err_with(|| format!("Failed to write checkpoint {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Delete the checkpoint once all the follows succeeded
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The followers that still need to be followed
+    pub fn remaining(&self) -> Vec<BridgedFollower> {
+        self.followers
+            .iter()
+            .filter(|f| !self.completed.contains(f.did.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn mark_completed(&mut self, follower: &BridgedFollower) -> Result<()> {
+        self.completed.insert(follower.did.as_str().to_string());
+        info!(
+            did = follower.did.as_str(),
+            "Checkpoint: {}/{} follows done",
+            self.completed.len(),
+            self.followers.len()
+        );
+        self.save()
+    }
+}
+
+/// Get the path of the checkpoint file of a profile, stored next to the configuration file
+pub fn checkpoint_path(config: &Config) -> PathBuf {
+    config.sibling_path("checkpoint.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Response};
+
+    /// Follow an account on a stub server answering with `response`, and classify the failure
+    async fn follow_failure(response: Option<Response>) -> FailureKind {
+        let server_url = test_server::start(move |_| response.clone()).await;
+        let client = MastodonClient::new(&server_url, "token".to_string()).unwrap();
+        let error = client
+            .follow_account_with_options("1", true, false, &[])
+            .await
+            .unwrap_err();
+        classify_failure(&error.wrap_err("Failed to follow account"))
+    }
+
+    #[tokio::test]
+    async fn rate_limited_with_retry_after() {
+        let kind = follow_failure(Some(Response::new(429).header("Retry-After", "30"))).await;
+        assert_eq!(
+            kind,
+            FailureKind::RateLimited(Some(Duration::from_secs(30)))
+        );
+        assert_eq!(retry_delay(kind, 0, None), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn rate_limited_without_retry_after() {
+        let kind = follow_failure(Some(Response::new(429))).await;
+        assert_eq!(kind, FailureKind::RateLimited(None));
+        assert_eq!(retry_delay(kind, 0, None), DEFAULT_RATE_LIMITED_DELAY);
+
+        // The window of the last successful response is waited for instead
+        let rate_limit = RateLimit {
+            remaining: 0,
+            reset: Timestamp::now() + Duration::from_secs(120),
+        };
+        let delay = retry_delay(kind, 0, Some(rate_limit));
+        assert!(delay > Duration::from_secs(110) && delay <= Duration::from_secs(120));
+    }
+
+    #[tokio::test]
+    async fn server_error_is_transient() {
+        let kind = follow_failure(Some(Response::new(503))).await;
+        assert_eq!(kind, FailureKind::Transient);
+        assert_eq!(retry_delay(kind, 0, None), INITIAL_RETRY_DELAY);
+        assert_eq!(retry_delay(kind, 2, None), INITIAL_RETRY_DELAY * 4);
+    }
+
+    #[tokio::test]
+    async fn not_found_is_permanent() {
+        let kind = follow_failure(Some(Response::new(404))).await;
+        assert_eq!(kind, FailureKind::Permanent);
+    }
+
+    #[tokio::test]
+    async fn timeout_is_transient() {
+        let server_url = test_server::start(|_| None).await;
+        let error = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap()
+            .post(format!("{server_url}/api/v1/accounts/1/follow"))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(classify_failure(&error.into()), FailureKind::Transient);
+    }
+}
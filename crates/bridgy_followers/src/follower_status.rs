@@ -1,77 +1,243 @@
 use crate::{
-    bluesky::{BlueskyAgent, get_bridgy_did, get_known_followers, get_relationships},
-    mastodon,
-    utils::{BRIDGY_ACTIVITY_PUB_URL, bluesky_handle_to_mastodon},
+    bluesky::{
+        BlueskyAgent, get_bridgy_did, get_followers, get_known_followers, get_list_members,
+        get_own_did, get_relationships, get_starter_pack_list,
+    },
+    config::Config,
+    mastodon::{self, Moderation},
+    state::State,
+    utils::{BRIDGY_ACTIVITY_PUB_URL, BRIDGY_MASTODON_DOMAIN, bluesky_handle_to_mastodon},
     webfinger,
 };
-use atrium_api::types::string::Handle;
+use atrium_api::{
+    app::bsky::{actor::defs::ProfileViewData, graph::defs::Relationship},
+    types::{
+        Object,
+        string::{Did, Handle},
+    },
+};
+use clap::ValueEnum;
 use color_eyre::Result;
+use futures::{StreamExt, stream};
 use ipld_core::ipld::Ipld;
 use megalodon::mastodon::Mastodon;
-use std::{collections::BTreeMap, io};
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, io,
+};
+use tracing::{info, warn};
 
 /// Represents a bridged follower with their current status
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BridgedFollower {
     pub handle: Handle,
+    pub did: Did,
+    /// The account address of the bridged account on Mastodon (e.g. "user.bsky.social@bsky.brid.gy")
+    pub mastodon_acct: String,
     pub status: FollowerStatus,
 }
 
 impl BridgedFollower {
-    pub fn new(handle: Handle, status: FollowerStatus) -> Self {
-        Self { handle, status }
+    pub fn new(handle: Handle, did: Did, status: FollowerStatus) -> Self {
+        let mastodon_acct = bluesky_handle_to_mastodon(&handle);
+        Self {
+            handle,
+            did,
+            mastodon_acct,
+            status,
+        }
+    }
+}
+
+/// The Bluesky accounts considered for following on Mastodon
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum FollowerSource {
+    /// Accounts followed on Bluesky
+    #[default]
+    Follows,
+    /// Accounts following me on Bluesky
+    Followers,
+    /// Accounts both following me and followed on Bluesky
+    Mutuals,
+}
+
+impl fmt::Display for FollowerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FollowerSource::Follows => write!(f, "follows"),
+            FollowerSource::Followers => write!(f, "followers"),
+            FollowerSource::Mutuals => write!(f, "mutuals"),
+        }
+    }
+}
+
+/// Where the Bluesky accounts considered for following on Mastodon come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountSource {
+    /// Accounts related to the user's own Bluesky account
+    Graph(FollowerSource),
+    /// Members of a Bluesky list, as an AT-URI or bsky.app URL
+    List(String),
+    /// Members of a Bluesky starter pack, as an AT-URI or bsky.app URL
+    StarterPack(String),
+}
+
+impl fmt::Display for AccountSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountSource::Graph(source) => write!(f, "{source}"),
+            AccountSource::List(list) => write!(f, "list {list}"),
+            AccountSource::StarterPack(starter_pack) => write!(f, "starter pack {starter_pack}"),
+        }
     }
 }
 
 /// The status of a bridged follower
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FollowerStatus {
     /// User is in the ignored accounts list
     Ignored,
     /// User is already followed on Mastodon
     AlreadyFollowedOnMastodon,
+    /// User is already followed on Bluesky (when syncing from Mastodon to Bluesky)
+    AlreadyFollowedOnBluesky,
+    /// The bridged account is blocked on Mastodon
+    BlockedOnMastodon,
+    /// The bridged account is muted on Mastodon
+    MutedOnMastodon,
+    /// The bridge domain is blocked on Mastodon
+    DomainBlocked,
     /// User is ready to be followed
     ReadyToFollow,
+    /// The bridged account couldn't be checked on the bridge (e.g. its WebFinger lookup kept timing out), it's
+    /// checked again on the next sync
+    Unverified,
     /// User is not bridged
     NotBridged(NotBridgedReason),
 }
 
 /// Specific reason why we found out that a user is not bridged
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NotBridgedReason {
     /// User blocks the Bridgy bridge account
     BlocksBridge,
     /// No relationship data available from API
     NoRelationshipData,
+    /// User doesn't follow the Bridgy bridge account, so they never opted in to bridging
+    NotFollowingBridge,
     // The webfinger lookup on bridgy returned no account
     NoAccountOnBridgy,
+    /// The fediverse account has no bridged profile on Bluesky (when syncing from Mastodon to Bluesky)
+    NoAccountOnBluesky,
+}
+
+impl fmt::Display for FollowerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FollowerStatus::Ignored => write!(f, "Ignored"),
+            FollowerStatus::AlreadyFollowedOnMastodon => write!(f, "Already followed on Mastodon"),
+            FollowerStatus::AlreadyFollowedOnBluesky => write!(f, "Already followed on Bluesky"),
+            FollowerStatus::BlockedOnMastodon => write!(f, "Blocked on Mastodon"),
+            FollowerStatus::MutedOnMastodon => write!(f, "Muted on Mastodon"),
+            FollowerStatus::DomainBlocked => write!(f, "Bridge domain blocked on Mastodon"),
+            FollowerStatus::ReadyToFollow => write!(f, "Ready to follow"),
+            FollowerStatus::Unverified => write!(f, "Couldn't be checked on the bridge"),
+            FollowerStatus::NotBridged(reason) => write!(f, "Not bridged ({reason})"),
+        }
+    }
+}
+
+impl fmt::Display for NotBridgedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotBridgedReason::BlocksBridge => write!(f, "blocks the bridge"),
+            NotBridgedReason::NoRelationshipData => write!(f, "no relationship with the bridge"),
+            NotBridgedReason::NotFollowingBridge => write!(f, "doesn't follow the bridge"),
+            NotBridgedReason::NoAccountOnBridgy => write!(f, "no account on the bridge"),
+            NotBridgedReason::NoAccountOnBluesky => write!(f, "no bridged profile on Bluesky"),
+        }
+    }
+}
+
+/// Get the Bluesky accounts of a source, before any filtering
+async fn get_source_accounts(
+    bluesky: &BlueskyAgent,
+    source: &AccountSource,
+    bridgy_did: &Did,
+) -> Result<Vec<Object<ProfileViewData>>> {
+    let accounts = match source {
+        // All users that follow the bridge account on Bluesky that the user's Bluesky account also follows
+        AccountSource::Graph(FollowerSource::Follows) => {
+            get_known_followers(bluesky, bridgy_did).await?
+        }
+        // All users that follow the user's Bluesky account, whether they follow the bridge is checked in pass 3
+        AccountSource::Graph(FollowerSource::Followers) => {
+            let own_did = get_own_did(bluesky).await?;
+            get_followers(bluesky, &own_did).await?
+        }
+        // The intersection of both, starting from the follows as they are already restricted to the bridge
+        AccountSource::Graph(FollowerSource::Mutuals) => {
+            let own_did = get_own_did(bluesky).await?;
+            let followers = get_followers(bluesky, &own_did).await?;
+            let mut follows = get_known_followers(bluesky, bridgy_did).await?;
+            follows.retain(|did, _| followers.contains_key(did));
+            follows
+        }
+        // Curated accounts, whether the user follows them or not. As with followers, pass 3 checks the bridge.
+        AccountSource::List(list) => get_list_members(bluesky, list).await?,
+        AccountSource::StarterPack(starter_pack) => {
+            let list = get_starter_pack_list(bluesky, starter_pack).await?;
+            get_list_members(bluesky, &list).await?
+        }
+    };
+
+    info!(%source, "Found {} account(s) in source", accounts.len());
+    Ok(accounts.into_values().collect())
 }
 
 pub async fn get_follower_statuses(
     mastodon_user: &Mastodon,
     bluesky: &BlueskyAgent,
-    ignored_accounts: &[String],
+    config: &Config,
+    state: &mut State,
+    source: &AccountSource,
     quiet: bool,
 ) -> Result<Vec<BridgedFollower>> {
-    let mastodon_following = mastodon::get_following(mastodon_user, quiet).await?;
+    let mastodon_following = mastodon::get_following_accounts(mastodon_user, quiet).await?;
+    let following_by_acct: HashMap<String, &str> = mastodon_following
+        .iter()
+        .map(|account| (account.acct.to_lowercase(), account.id.as_str()))
+        .collect();
+    let following_ids: HashSet<&str> = following_by_acct.values().copied().collect();
+    let mastodon_moderation = mastodon::get_moderation(mastodon_user)
+        .await?
+        .unwrap_or_else(|| {
+            warn!(
+                "The Mastodon access token can't read blocks and mutes, accounts blocked or muted on Mastodon aren't filtered. Run `forget` to log in again with the `read:blocks` and `read:mutes` scopes"
+            );
+            Moderation::default()
+        });
 
-    // Start the process with all users that the bridge account follows on Bluesky that the user's Bluesky account
-    // also follows
+    // Start the process with all the accounts of the selected source
     let bridgy_did = get_bridgy_did(bluesky).await?;
-    let bridgy_followers = get_known_followers(bluesky, &bridgy_did).await?;
-    let to_process = bridgy_followers.values();
+    let to_process = get_source_accounts(bluesky, source, &bridgy_did).await?;
 
     let mut result = Vec::<BridgedFollower>::new();
 
     // ----------------------------------------------------------------------
     // Pass 1: filter accounts ignored in the configuration or already followed on Mastodon
     // This is the cheapest check, we have all the data to find out right away if we need to process further
+    // Accounts are matched by DID and by the id of their bridged account on Mastodon when we know it, so that handle
+    // changes don't break ignores or cause a second follow.
     let to_process: Vec<_> = to_process
+        .into_iter()
         .filter(|bsky_user| {
-            let ignored = ignored_accounts
-                .iter()
-                .any(|handle| handle == bsky_user.handle.as_str());
+            let ignored =
+                config.is_ignored(Some(bsky_user.did.as_str()), bsky_user.handle.as_str());
             if ignored {
                 info!(
                     did = bsky_user.did.as_str(),
@@ -80,23 +246,32 @@ pub async fn get_follower_statuses(
                 );
                 result.push(BridgedFollower::new(
                     bsky_user.handle.clone(),
+                    bsky_user.did.clone(),
                     FollowerStatus::Ignored,
                 ));
                 return false;
             }
 
             let mastodon_handle = bluesky_handle_to_mastodon(&bsky_user.handle);
-            let already_following = mastodon_following.contains(&mastodon_handle);
-            if already_following {
+            let following_id = state
+                .mastodon_id(&bsky_user.did)
+                .filter(|id| following_ids.contains(id))
+                .or_else(|| following_by_acct.get(&mastodon_handle).copied())
+                .map(ToString::to_string);
+            if let Some(following_id) = following_id {
                 info!(
                     did = bsky_user.did.as_str(),
+                    mastodon_id = following_id.as_str(),
                     "User '{}' already followed on Mastodon as {mastodon_handle}",
                     bsky_user.handle.as_str()
                 );
-                result.push(BridgedFollower::new(
+                let follower = BridgedFollower::new(
                     bsky_user.handle.clone(),
+                    bsky_user.did.clone(),
                     FollowerStatus::AlreadyFollowedOnMastodon,
-                ));
+                );
+                state.record_mastodon_id(&follower, &following_id);
+                result.push(follower);
                 return false;
             }
 
@@ -105,9 +280,85 @@ pub async fn get_follower_statuses(
         .collect();
 
     // ----------------------------------------------------------------------
-    // Pass 2: relationship checks
-    // Check if the user is really followed by the bridge (It should be the case if get_known_followers returned it)
-    // and if the user doesn't block the bridge either directly or via a block list as it would prevent bridging.
+    // Pass 2: filter accounts blocked or muted on Mastodon, directly or by blocking the whole bridge domain
+    // Following them would undo a moderation decision taken on Mastodon.
+    let domain_blocked = mastodon_moderation.blocks_domain(BRIDGY_MASTODON_DOMAIN);
+
+    let to_process: Vec<_> = to_process
+        .into_iter()
+        .filter(|bsky_user| {
+            let mastodon_handle = bluesky_handle_to_mastodon(&bsky_user.handle);
+            let status = if domain_blocked {
+                FollowerStatus::DomainBlocked
+            } else if mastodon_moderation.blocked.contains(&mastodon_handle) {
+                FollowerStatus::BlockedOnMastodon
+            } else if mastodon_moderation.muted.contains(&mastodon_handle) {
+                FollowerStatus::MutedOnMastodon
+            } else {
+                return true;
+            };
+
+            info!(
+                did = bsky_user.did.as_str(),
+                "User '{}' filtered by Mastodon moderation: {status}",
+                bsky_user.handle.as_str()
+            );
+            result.push(BridgedFollower::new(
+                bsky_user.handle.clone(),
+                bsky_user.did.clone(),
+                status,
+            ));
+            false
+        })
+        .collect();
+
+    let to_process = to_process
+        .into_iter()
+        .map(|bsky_user| BlueskyAccount {
+            handle: bsky_user.handle.clone(),
+            did: bsky_user.did.clone(),
+        })
+        .collect();
+    result.extend(check_bridged(bluesky, config, &bridgy_did, to_process).await?);
+
+    Ok(result)
+}
+
+/// Whether the other account of a relationship blocks the actor, either directly or via a block list
+pub fn blocks_actor(relationship: &Relationship) -> bool {
+    // Blocks are a Recent adition to the Lexicon, not yet in atrium
+    // https://github.com/bluesky-social/atproto/pull/4418
+    let extra_data: BTreeMap<String, Ipld> = relationship
+        .extra_data
+        .clone()
+        .try_into()
+        .unwrap_or_default();
+    extra_data.contains_key("blockedBy") || extra_data.contains_key("blockedByList")
+}
+
+/// A Bluesky account to check with the bridge
+#[derive(Debug, Clone)]
+pub struct BlueskyAccount {
+    pub handle: Handle,
+    pub did: Did,
+}
+
+/// Check with the bridge whether accounts are bridged, these are the last passes of [`get_follower_statuses`].
+///
+/// Bridged accounts get the `ReadyToFollow` status, the others the reason they aren't bridged.
+pub async fn check_bridged(
+    bluesky: &BlueskyAgent,
+    config: &Config,
+    bridgy_did: &Did,
+    to_process: Vec<BlueskyAccount>,
+) -> Result<Vec<BridgedFollower>> {
+    let mut result = Vec::<BridgedFollower>::new();
+
+    // ----------------------------------------------------------------------
+    // Pass 3: relationship checks
+    // Check if the user is really followed by the bridge (It should be the case if get_known_followers returned it,
+    // but not for the other sources) and if the user doesn't block the bridge either directly or via a block list
+    // as it would prevent bridging.
     // This remove users that activated bridging but then deactivated it by blocking the bridge.
 
     let relationships = get_relationships(
@@ -119,71 +370,114 @@ pub async fn get_follower_statuses(
 
     let to_process: Vec<_> = to_process
         .into_iter()
-        .filter(|bsky_user| {
-            match relationships.get(&bsky_user.did) {
-                None => {
+        .filter(|bsky_user| match relationships.get(&bsky_user.did) {
+            None => {
+                info!(
+                    did = bsky_user.did.as_str(),
+                    "User '{}' has no relationship with the bridge",
+                    bsky_user.handle.as_str()
+                );
+                result.push(BridgedFollower::new(
+                    bsky_user.handle.clone(),
+                    bsky_user.did.clone(),
+                    FollowerStatus::NotBridged(NotBridgedReason::NoRelationshipData),
+                ));
+                false
+            }
+            Some(relationship) => {
+                let blocks_bridge = blocks_actor(relationship);
+                let followed_by_bridge = relationship.followed_by.is_some();
+
+                if blocks_bridge {
                     info!(
+                        ?followed_by_bridge,
+                        ?blocks_bridge,
                         did = bsky_user.did.as_str(),
-                        "User '{}' has no relationship with the bridge",
+                        "User '{}' blocks the bridge, filtering",
                         bsky_user.handle.as_str()
                     );
                     result.push(BridgedFollower::new(
                         bsky_user.handle.clone(),
-                        FollowerStatus::NotBridged(NotBridgedReason::NoRelationshipData),
+                        bsky_user.did.clone(),
+                        FollowerStatus::NotBridged(NotBridgedReason::BlocksBridge),
                     ));
-                    false
+                    return false;
                 }
-                Some(relationship) => {
-                    // Blocks are a Recent adition to the Lexicon, not yet in atrium
-                    // https://github.com/bluesky-social/atproto/pull/4418
-                    let extra_data: BTreeMap<String, Ipld> = relationship
-                        .extra_data
-                        .clone()
-                        .try_into()
-                        .unwrap_or_default();
-                    let blocks_bridge = extra_data.contains_key("blockedBy")
-                        || extra_data.contains_key("blockedByList");
-
-                    let followed_by_bridge = relationship.followed_by.is_some();
-
-                    if blocks_bridge {
-                        info!(
-                            ?followed_by_bridge,
-                            ?blocks_bridge,
-                            did = bsky_user.did.as_str(),
-                            "User '{}' blocks the bridge, filtering",
-                            bsky_user.handle.as_str()
-                        );
-                        result.push(BridgedFollower::new(
-                            bsky_user.handle.clone(),
-                            FollowerStatus::NotBridged(NotBridgedReason::BlocksBridge),
-                        ));
-                        return false;
-                    }
 
+                if !followed_by_bridge {
                     info!(
-                        ?followed_by_bridge,
-                        ?blocks_bridge,
                         did = bsky_user.did.as_str(),
-                        "Need to add new user '{}'",
+                        "User '{}' doesn't follow the bridge, filtering",
                         bsky_user.handle.as_str()
                     );
-                    true
+                    result.push(BridgedFollower::new(
+                        bsky_user.handle.clone(),
+                        bsky_user.did.clone(),
+                        FollowerStatus::NotBridged(NotBridgedReason::NotFollowingBridge),
+                    ));
+                    return false;
                 }
+
+                info!(
+                    ?followed_by_bridge,
+                    ?blocks_bridge,
+                    did = bsky_user.did.as_str(),
+                    "Need to add new user '{}'",
+                    bsky_user.handle.as_str()
+                );
+                true
             }
         })
         .collect();
 
     // ----------------------------------------------------------------------
-    // Pass 3: for all potential new follows check that the user is really bridged by directly querying their profile
+    // Pass 4: for all potential new follows check that the user is really bridged by directly querying their profile
     // using the webfinger endpoint of the bridge (acting as an Activity Pub server)
     // This remove users that activated bridging but then deactivated it via the web interface.
+    // The lookups are independent so they are done concurrently over a shared connection pool.
 
-    for bsky_user in to_process {
-        let mastodon_handle = bluesky_handle_to_mastodon(&bsky_user.handle);
+    let concurrency = config.webfinger_concurrency();
+    let http_client = webfinger::create_client(config.webfinger_timeout(), concurrency)?;
+
+    let lookups: Vec<_> = stream::iter(to_process)
+        .map(|bsky_user| {
+            let http_client = &http_client;
+            async move {
+                let mastodon_handle = bluesky_handle_to_mastodon(&bsky_user.handle);
+                let account_exists = webfinger::account_exists(
+                    http_client,
+                    BRIDGY_ACTIVITY_PUB_URL,
+                    &mastodon_handle,
+                )
+                .await;
+                (bsky_user, account_exists)
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    // A failed lookup only skips its account, the others can still be followed
+    let mut unverified_count = 0;
+    for (bsky_user, account_exists) in lookups {
+        let account_exists = match account_exists {
+            Ok(account_exists) => account_exists,
+            Err(e) => {
+                warn!(
+                    did = bsky_user.did.as_str(),
+                    "Couldn't check '{}' on the bridge, skipping: {e}",
+                    bsky_user.handle.as_str()
+                );
+                result.push(BridgedFollower::new(
+                    bsky_user.handle.clone(),
+                    bsky_user.did.clone(),
+                    FollowerStatus::Unverified,
+                ));
+                unverified_count += 1;
+                continue;
+            }
+        };
 
-        let account_exists =
-            webfinger::account_exists(BRIDGY_ACTIVITY_PUB_URL, &mastodon_handle).await?;
         if account_exists {
             info!(
                 did = bsky_user.did.as_str(),
@@ -192,6 +486,7 @@ pub async fn get_follower_statuses(
             );
             result.push(BridgedFollower::new(
                 bsky_user.handle.clone(),
+                bsky_user.did.clone(),
                 FollowerStatus::ReadyToFollow,
             ));
         } else {
@@ -202,17 +497,25 @@ pub async fn get_follower_statuses(
             );
             result.push(BridgedFollower::new(
                 bsky_user.handle.clone(),
+                bsky_user.did.clone(),
                 FollowerStatus::NotBridged(NotBridgedReason::NoAccountOnBridgy),
             ));
         }
     }
 
+    if unverified_count > 0 {
+        warn!(
+            "Couldn't check {unverified_count} account(s) on the bridge, they will be checked again on the next sync"
+        );
+    }
+
     Ok(result)
 }
 
 pub fn write_statuses_to_import_csv<W>(
     csv_writer: &mut csv::Writer<W>,
     statuses: &[BridgedFollower],
+    config: &Config,
 ) -> csv::Result<()>
 where
     W: io::Write,
@@ -226,12 +529,12 @@ where
 
     for status in statuses {
         if status.status == FollowerStatus::ReadyToFollow {
-            let mastodon_handle = crate::utils::bluesky_handle_to_mastodon(&status.handle);
+            let options = config.follow_options_for(status.handle.as_str(), status.did.as_str());
             csv_writer.write_record(&[
-                format!("@{mastodon_handle}"),
-                "true".to_string(),
-                "false".to_string(),
-                "".to_string(),
+                format!("@{}", status.mastodon_acct),
+                options.reblogs.to_string(),
+                options.notify.to_string(),
+                options.languages.join(", "),
             ])?;
         }
     }
@@ -239,9 +542,9 @@ where
     Ok(())
 }
 
-pub fn statuses_to_import_csv(statuses: &[BridgedFollower]) -> Result<String> {
+pub fn statuses_to_import_csv(statuses: &[BridgedFollower], config: &Config) -> Result<String> {
     let mut csv_writer = csv::Writer::from_writer(vec![]);
-    write_statuses_to_import_csv(&mut csv_writer, statuses)?;
+    write_statuses_to_import_csv(&mut csv_writer, statuses, config)?;
 
     let data = csv_writer.into_inner()?;
     Ok(String::from_utf8(data)?)
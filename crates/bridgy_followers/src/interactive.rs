@@ -0,0 +1,56 @@
+use crate::exit_code::AuthRequired;
+use color_eyre::{Result, eyre::bail};
+use std::{
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+static NON_INTERACTIVE_FLAG: AtomicBool = AtomicBool::new(false);
+
+/// Disable prompts when asked to, or when stdin isn't a terminal (e.g. when run from cron)
+pub fn init(non_interactive: bool) {
+    NON_INTERACTIVE_FLAG.store(non_interactive, Ordering::Relaxed);
+    NON_INTERACTIVE.store(
+        non_interactive || !io::stdin().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+/// Whether `--non-interactive` was passed, unlike [`is_interactive`] this doesn't depend on stdin
+pub fn non_interactive_flag() -> bool {
+    NON_INTERACTIVE_FLAG.load(Ordering::Relaxed)
+}
+
+pub fn is_interactive() -> bool {
+    !NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Fail instead of prompting for a configuration setting
+pub fn require_setting(key: &str) -> Result<()> {
+    if !is_interactive() {
+        bail!(
+            "`{key}` isn't set in the configuration and prompts are disabled in non-interactive mode"
+        );
+    }
+    Ok(())
+}
+
+/// Fail instead of prompting for a credential or authorizing in the browser
+pub fn require_credential(credential: &str) -> Result<()> {
+    if !is_interactive() {
+        return Err(AuthRequired(format!(
+            "No {credential} in the credential store and prompts are disabled in non-interactive mode, run the command interactively to log in"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Fail instead of asking the user to select accounts, `alternative` tells how to do without the prompt
+pub fn require_selection(alternative: &str) -> Result<()> {
+    if !is_interactive() {
+        bail!("Prompts are disabled in non-interactive mode, {alternative}");
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use color_eyre::{Result, eyre::bail};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::debug;
+use url::Url;
+
+/// How long a connection can take to send its request, so that a client sending nothing doesn't block the listener
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accept connections on a loopback listener until the authorization server redirects the browser to `path`, and
+/// return the query parameters of the redirect. An `error` parameter is reported as a failed authorization. Any other
+/// request (e.g. a favicon probe or a port scan) is ignored.
+pub async fn wait_for_redirect(
+    listener: &TcpListener,
+    path: &str,
+    service: &str,
+) -> Result<BTreeMap<String, String>> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+
+        // The request line is all we need, browsers send the whole request head in a single packet
+        let mut buffer = vec![0; 8192];
+        let read = match tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buffer)).await {
+            Ok(Ok(read)) => read,
+            Ok(Err(e)) => {
+                debug!("Failed to read a request on the OAuth callback listener: {e}");
+                continue;
+            }
+            Err(_) => {
+                debug!("Timed out reading a request on the OAuth callback listener");
+                continue;
+            }
+        };
+        let request = String::from_utf8_lossy(&buffer[..read]);
+        let Some(target) = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+        else {
+            continue;
+        };
+
+        let url = match Url::parse(&format!("http://127.0.0.1{target}")) {
+            Ok(url) if url.path() == path => url,
+            _ => {
+                debug!("Ignoring request to {target} on the OAuth callback listener");
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await;
+                continue;
+            }
+        };
+
+        let query: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+
+        let (status, message) = if query.contains_key("error") {
+            (
+                "400 Bad Request",
+                "Authorization failed, you can close this window.",
+            )
+        } else {
+            (
+                "200 OK",
+                "Authorization complete, you can close this window.",
+            )
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{message}",
+            message.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+
+        if let Some(error) = query.get("error") {
+            let description = query
+                .get("error_description")
+                .map(String::as_str)
+                .unwrap_or_default();
+            bail!("{service} authorization failed: {error} {description}");
+        }
+
+        return Ok(query);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    /// Send a request to the listener like a browser would, and return the response
+    async fn get(port: u16, target: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!("GET {target} HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn listener() -> (TcpListener, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    #[tokio::test]
+    async fn returns_query_of_redirect() {
+        let (listener, port) = listener().await;
+        let redirect =
+            tokio::spawn(async move { wait_for_redirect(&listener, "/callback", "Test").await });
+
+        // Requests to other paths (e.g. the favicon) are ignored
+        let response = get(port, "/favicon.ico").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = get(
+            port,
+            "/callback?code=abc%2B1&state=xyz&iss=https%3A%2F%2Fbsky.social",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let query = redirect.await.unwrap().unwrap();
+        assert_eq!(query.get("code").map(String::as_str), Some("abc+1"));
+        assert_eq!(query.get("state").map(String::as_str), Some("xyz"));
+        assert_eq!(
+            query.get("iss").map(String::as_str),
+            Some("https://bsky.social")
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_malformed_requests() {
+        let (listener, port) = listener().await;
+        let redirect =
+            tokio::spawn(async move { wait_for_redirect(&listener, "/callback", "Test").await });
+
+        // A client closing the connection without sending anything
+        drop(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+
+        // A target that isn't a valid URL path
+        let response = get(port, ":99999/callback").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = get(port, "/callback?code=abc&state=xyz").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let query = redirect.await.unwrap().unwrap();
+        assert_eq!(query.get("code").map(String::as_str), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_redirect() {
+        let (listener, port) = listener().await;
+        let redirect =
+            tokio::spawn(async move { wait_for_redirect(&listener, "/callback", "Test").await });
+
+        let response = get(
+            port,
+            "/callback?error=access_denied&error_description=Denied",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let error = redirect.await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("access_denied"));
+    }
+}
@@ -1,39 +1,140 @@
 #![allow(clippy::borrowed_box, reason = "Trigger on &Box<dyn Trait> parameters")]
 
-use crate::cli_args::{CliArgs, Command, IgnoresCommand};
+use crate::cli_args::{
+    CliArgs, Command, IgnoresCommand, ModerationCommand, ProfilesCommand, account_source,
+};
 use crate::commands::{
-    config_command, csv_command, forget_command, ignores_add_command, ignores_list_command,
-    sync_command,
+    apply_command, backfill_list_command, config_command, csv_command, explain_command,
+    forget_command, history_command, ignores_add_command, ignores_list_command,
+    moderation_sync_command, profiles_add_command, prune_command, reconcile_command,
+    repair_command, report_command, sync_all_profiles_command, sync_command, watch_command,
 };
 use clap::Parser;
 use color_eyre::Result;
+use exit_code::Outcome;
+use std::process::ExitCode;
 
+mod activitypub;
 mod bluesky;
 mod cli_args;
 mod commands;
 mod config;
 mod credentials;
+mod exit_code;
+mod fediverse_status;
+mod follow_executor;
 mod follower_status;
+mod interactive;
+mod loopback;
 mod mastodon;
+mod plan;
+mod report;
+mod state;
+#[cfg(test)]
+mod test_server;
 mod tracing;
 mod utils;
+mod watch;
 mod webfinger;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     color_eyre::install()?;
 
     let cli = CliArgs::parse();
     tracing::init_tracing(cli.command.verbose());
 
-    match cli.command {
-        Command::Sync { config, .. } => sync_command(config, None).await,
-        Command::Csv { config, output, .. } => csv_command(config, output).await,
-        Command::Forget { config, .. } => forget_command(&config),
+    interactive::init(cli.non_interactive);
+
+    let profile = cli.profile.as_deref();
+    let result = match cli.command {
+        Command::Sync {
+            config,
+            direction,
+            source,
+            from_list,
+            from_starter_pack,
+            dry_run,
+            plan,
+            resume,
+            all_profiles,
+            ..
+        } => {
+            let source = account_source(source, from_list, from_starter_pack);
+            if all_profiles {
+                sync_all_profiles_command(config, direction, source, dry_run).await
+            } else {
+                sync_command(config, profile, direction, source, dry_run, plan, resume).await
+            }
+        }
+        Command::Watch {
+            config, interval, ..
+        } => watch_command(config, profile, interval).await,
+        Command::Apply { plan, config, .. } => apply_command(config, profile, &plan).await,
+        Command::Reconcile {
+            config,
+            include_manual,
+            yes,
+            ..
+        } => reconcile_command(config, profile, include_manual, yes).await,
+        Command::Prune {
+            config,
+            unfollow,
+            yes,
+            ..
+        } => prune_command(config, profile, unfollow, yes).await,
+        Command::Repair { config, yes, .. } => repair_command(config, profile, yes).await,
+        Command::BackfillList { config, .. } => backfill_list_command(config, profile).await,
+        Command::Moderation { command } => match command {
+            ModerationCommand::Sync {
+                config, dry_run, ..
+            } => moderation_sync_command(config, profile, dry_run).await,
+        },
+        Command::Csv {
+            config,
+            output,
+            source,
+            ..
+        } => csv_command(config, profile, output, source)
+            .await
+            .map(Outcome::from),
+        Command::Report {
+            config,
+            format,
+            output,
+            source,
+            ..
+        } => report_command(config, profile, format, output, source)
+            .await
+            .map(Outcome::from),
+        Command::Explain {
+            account, config, ..
+        } => explain_command(config, profile, &account)
+            .await
+            .map(Outcome::from),
+        Command::History {
+            account,
+            at,
+            config,
+            ..
+        } => {
+            history_command(&config, profile, account.as_deref(), at.as_deref()).map(Outcome::from)
+        }
+        Command::Forget { config, .. } => forget_command(&config, profile).await.map(Outcome::from),
         Command::Ignores { command } => match command {
-            IgnoresCommand::List { config, .. } => ignores_list_command(&config),
-            IgnoresCommand::Add { account, .. } => ignores_add_command(account).await,
+            IgnoresCommand::List { config, .. } => {
+                ignores_list_command(&config, profile).map(Outcome::from)
+            }
+            IgnoresCommand::Add { account, .. } => ignores_add_command(account, profile)
+                .await
+                .map(Outcome::from),
+        },
+        Command::Profiles { command } => match command {
+            ProfilesCommand::Add { name, config, .. } => {
+                profiles_add_command(&config, &name).map(Outcome::from)
+            }
         },
-        Command::Config { .. } => config_command(),
-    }
+        Command::Config { .. } => config_command().map(Outcome::from),
+    };
+    exit_code::from_result(result)
 }
@@ -0,0 +1,113 @@
+use color_eyre::{Result, eyre::WrapErr};
+use megalodon::mastodon::Mastodon;
+use reqwest::{StatusCode, header::HeaderMap};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use tracing::instrument;
+
+use super::{
+    rate_limit::RateLimited,
+    utils::{USER_AGENT, create_client},
+};
+
+/// An authenticated Mastodon client.
+///
+/// Most calls go through megalodon via `Deref`, the few parts of the API that megalodon doesn't expose are called
+/// directly using the access token.
+pub struct MastodonClient {
+    client: Mastodon,
+    server_url: String,
+    access_token: String,
+    http_client: reqwest::Client,
+}
+
+impl Deref for MastodonClient {
+    type Target = Mastodon;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+#[derive(Serialize)]
+struct FollowParams<'a> {
+    reblogs: bool,
+    notify: bool,
+    /// An empty list would hide every post, leaving it out shows all languages
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    languages: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct Application {
+    #[serde(default)]
+    scopes: Option<Vec<String>>,
+}
+
+impl MastodonClient {
+    pub fn new(server_url: &str, access_token: String) -> Result<Self> {
+        let client = create_client(server_url, Some(access_token.clone()))?;
+        let http_client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .build()
+            .wrap_err("Failed to create Mastodon HTTP client")?;
+
+        Ok(Self {
+            client,
+            server_url: server_url.trim_end_matches('/').to_string(),
+            access_token,
+            http_client,
+        })
+    }
+
+    /// Follow an account with its options, including language filtering which megalodon's follow options don't
+    /// support. Megalodon also drops the headers of failed responses, which tell how long to wait when rate limited.
+    ///
+    /// Returns the headers of the response.
+    #[instrument(skip(self))]
+    pub async fn follow_account_with_options(
+        &self,
+        account_id: &str,
+        reblogs: bool,
+        notify: bool,
+        languages: &[String],
+    ) -> Result<HeaderMap> {
+        let url = format!("{}/api/v1/accounts/{account_id}/follow", self.server_url);
+        let response = self
+            .http_client
+            .post(url)
+            .bearer_auth(&self.access_token)
+            .json(&FollowParams {
+                reblogs,
+                notify,
+                languages,
+            })
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(RateLimited::from_headers(response.headers()).into());
+        }
+        let response = response.error_for_status()?;
+
+        Ok(response.headers().clone())
+    }
+
+    /// Scopes the application of the access token was registered with, `None` if the server doesn't return them
+    /// (before Mastodon 4.3). These aren't the scopes of the token itself, which Mastodon doesn't expose, but a token
+    /// is never granted more than its application.
+    #[instrument(skip(self))]
+    pub async fn application_scopes(&self) -> Result<Option<Vec<String>>> {
+        let url = format!("{}/api/v1/apps/verify_credentials", self.server_url);
+        let application: Application = self
+            .http_client
+            .get(url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(application.scopes)
+    }
+}
@@ -1,5 +1,15 @@
+mod client;
+mod oauth;
+mod rate_limit;
 mod tui;
 mod utils;
 
-pub use tui::{authenticate, get_following};
-pub use utils::follow_account;
+pub use client::MastodonClient;
+pub use oauth::revoke as revoke_token;
+pub use rate_limit::{RateLimit, RateLimited};
+pub use tui::{authenticate, get_following_accounts};
+pub use utils::{
+    FollowedAccount, Moderation, add_accounts_to_list, block_account, find_account, follow_account,
+    get_list_account_ids, get_moderation, get_or_create_list, get_relationship, lookup_account,
+    lookup_account_id, mute_account, server_url, unblock_account, unfollow_account, unmute_account,
+};
@@ -0,0 +1,337 @@
+use super::utils::{USER_AGENT, create_client};
+use crate::loopback;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail, eyre},
+};
+use keyring::Credential;
+use megalodon::{Megalodon, megalodon::AppInputOptions};
+use rand::{Rng, distr::Alphanumeric};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{net::Ipv4Addr, time::Duration};
+use tokio::net::TcpListener;
+use tracing::{debug, warn};
+use url::Url;
+
+/// Port of the loopback redirect used by registrations made before it was configurable
+const DEFAULT_CALLBACK_PORT: u16 = 8428;
+const CALLBACK_PATH: &str = "/callback";
+
+/// How long to wait for the user to authorize the application in their browser
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+const SCOPES: &[&str] = &[
+    "read:accounts",
+    "read:follows",
+    "write:follows",
+    "read:lists",
+    "write:lists",
+    "read:blocks",
+    "write:blocks",
+    "read:mutes",
+    "write:mutes",
+];
+
+/// Scopes covered by the deprecated `follow` scope
+const FOLLOW_SCOPES: &[&str] = &[
+    "read:follows",
+    "write:follows",
+    "read:blocks",
+    "write:blocks",
+    "read:mutes",
+    "write:mutes",
+];
+
+/// The scopes of [`SCOPES`] that the given scopes don't grant, either directly or through the broader `read`, `write`
+/// and `follow` scopes
+pub fn missing_scopes<'a>(granted: impl Iterator<Item = &'a str>) -> Vec<&'static str> {
+    let granted: Vec<&str> = granted.collect();
+    SCOPES
+        .iter()
+        .copied()
+        .filter(|scope| {
+            let (access, _) = scope.split_once(':').unwrap_or((scope, ""));
+            !granted.iter().any(|granted| {
+                *granted == *scope
+                    || *granted == access
+                    || (*granted == "follow" && FOLLOW_SCOPES.contains(scope))
+            })
+        })
+        .collect()
+}
+
+/// The application registered on a Mastodon server, kept in the credential store
+#[derive(Debug, Serialize, Deserialize)]
+struct ClientRegistration {
+    client_id: String,
+    client_secret: String,
+    /// The redirect URI the application was registered with, the port of the callback is part of it
+    #[serde(default = "default_redirect_uri")]
+    redirect_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+fn redirect_uri(callback_port: u16) -> String {
+    format!("http://127.0.0.1:{callback_port}{CALLBACK_PATH}")
+}
+
+fn default_redirect_uri() -> String {
+    redirect_uri(DEFAULT_CALLBACK_PORT)
+}
+
+fn random_string(length: usize) -> String {
+    rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// A PKCE code verifier and its S256 challenge
+fn pkce() -> (String, String) {
+    let verifier = random_string(64);
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(Duration::from_secs(30))
+        .build()
+        .wrap_err("Failed to create Mastodon HTTP client")
+}
+
+async fn register(server_url: &str, redirect_uri: String) -> Result<ClientRegistration> {
+    println!("Registering application...");
+    let client = create_client(server_url, None)?;
+    let app_data = client
+        .register_app(
+            String::from("Bridgy Followers"),
+            &AppInputOptions {
+                scopes: Some(SCOPES.iter().map(ToString::to_string).collect()),
+                redirect_uris: Some(redirect_uri.clone()),
+                website: None,
+            },
+        )
+        .await
+        .wrap_err("Failed to register app")?;
+
+    Ok(ClientRegistration {
+        client_id: app_data.client_id,
+        client_secret: app_data.client_secret,
+        redirect_uri,
+    })
+}
+
+/// Whether the server still knows the application, it's gone if an admin deleted it. The server is asked for an
+/// application token, which is revoked right away so that logins don't pile up tokens on the server.
+async fn is_registered(
+    http_client: &reqwest::Client,
+    server_url: &str,
+    registration: &ClientRegistration,
+) -> Result<bool> {
+    let response = http_client
+        .post(format!("{server_url}/oauth/token"))
+        .json(&json!({
+            "grant_type": "client_credentials",
+            "client_id": registration.client_id,
+            "client_secret": registration.client_secret,
+            "scope": "read:accounts",
+        }))
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::UNAUTHORIZED {
+        return Ok(false);
+    }
+    let token: TokenResponse = response.error_for_status()?.json().await?;
+
+    let revoked = http_client
+        .post(format!("{server_url}/oauth/revoke"))
+        .json(&json!({
+            "client_id": registration.client_id,
+            "client_secret": registration.client_secret,
+            "token": token.access_token,
+        }))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    if let Err(e) = revoked {
+        warn!("Failed to revoke the application token used to check the registration: {e}");
+    }
+
+    Ok(true)
+}
+
+fn load_registration(credential: &Credential) -> Result<Option<ClientRegistration>> {
+    match credential.get_password() {
+        Ok(json) => serde_json::from_str(&json)
+            .map(Some)
+            .wrap_err("Invalid Mastodon application registration in the credential store"),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).wrap_err("Failed to access credential store"),
+    }
+}
+
+/// Forget the stored application registration so that the next login registers the application again
+pub fn forget_registration(credential: &Credential) -> Result<()> {
+    match credential.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry | keyring::Error::NotSupportedByStore(_)) => Ok(()),
+        Err(e) => Err(e).wrap_err("Failed to delete the application registration"),
+    }
+}
+
+/// The application registered on the server, registering it the first time. The registration is kept in the
+/// credential store so that logging in again doesn't create another application on the server, unless the callback
+/// port changed since the redirect URI can't be updated.
+async fn registration(
+    credential: &Credential,
+    http_client: &reqwest::Client,
+    server_url: &str,
+    redirect_uri: String,
+) -> Result<ClientRegistration> {
+    if let Some(registration) = load_registration(credential)? {
+        if registration.redirect_uri != redirect_uri {
+            warn!(
+                "The application registered on {server_url} redirects to {}, registering it again for {redirect_uri}",
+                registration.redirect_uri
+            );
+        } else if is_registered(http_client, server_url, &registration).await? {
+            debug!("Reusing the application registered on {server_url}");
+            return Ok(registration);
+        } else {
+            warn!(
+                "The application registered on {server_url} doesn't exist anymore, registering it again"
+            );
+        }
+    }
+
+    let registration = register(server_url, redirect_uri).await?;
+    match credential.set_password(&serde_json::to_string(&registration)?) {
+        Ok(()) => {}
+        // Read-only credential stores only provide the access token
+        Err(keyring::Error::NotSupportedByStore(e)) => {
+            warn!(
+                "The application registration can't be kept, it will be registered again next time: {e}"
+            )
+        }
+        Err(e) => return Err(e).wrap_err("Failed to store the application registration"),
+    }
+    Ok(registration)
+}
+
+/// Authorize the application in the user's browser with the authorization code flow and PKCE, the code is received on
+/// a loopback listener on `callback_port`. Returns the access token.
+pub async fn login(
+    client_credential: &Credential,
+    server_url: &str,
+    callback_port: u16,
+) -> Result<String> {
+    let server_url = server_url.trim_end_matches('/');
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, callback_port))
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Failed to listen on port {callback_port} for the OAuth callback, set `mastodon_oauth_callback_port` to use another port"
+            )
+        })?;
+
+    let http_client = http_client()?;
+    let registration = registration(
+        client_credential,
+        &http_client,
+        server_url,
+        redirect_uri(callback_port),
+    )
+    .await?;
+
+    let (verifier, challenge) = pkce();
+    let state = random_string(32);
+    let mut authorize_url = Url::parse(&format!("{server_url}/oauth/authorize"))?;
+    authorize_url
+        .query_pairs_mut()
+        .append_pair("client_id", &registration.client_id)
+        .append_pair("redirect_uri", &registration.redirect_uri)
+        .append_pair("response_type", "code")
+        .append_pair("scope", &SCOPES.join(" "))
+        .append_pair("state", &state)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    println!(
+        "\nPlease open this URL in your browser to authorize Bridgy Followers:\n{authorize_url}\n"
+    );
+
+    let query = tokio::time::timeout(
+        CALLBACK_TIMEOUT,
+        loopback::wait_for_redirect(&listener, CALLBACK_PATH, "Mastodon"),
+    )
+    .await
+    .map_err(|_| eyre!("Timed out waiting for the Mastodon authorization"))??;
+
+    if query.get("state") != Some(&state) {
+        bail!("Mastodon authorization callback has an unexpected state");
+    }
+    let Some(code) = query.get("code") else {
+        bail!("Mastodon authorization callback is missing the code");
+    };
+
+    println!("Getting access token...");
+    let token: TokenResponse = http_client
+        .post(format!("{server_url}/oauth/token"))
+        .json(&json!({
+            "grant_type": "authorization_code",
+            "code": code,
+            "client_id": registration.client_id,
+            "client_secret": registration.client_secret,
+            "redirect_uri": registration.redirect_uri,
+            "code_verifier": verifier,
+        }))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .wrap_err("Failed to fetch access token")?
+        .json()
+        .await?;
+
+    if let Some(scope) = &token.scope {
+        let missing = missing_scopes(scope.split_whitespace());
+        if !missing.is_empty() {
+            bail!(
+                "The access token was only granted the '{scope}' scopes, {} are missing",
+                missing.join(", ")
+            );
+        }
+    }
+
+    println!("✓ Authentication successful!");
+
+    Ok(token.access_token)
+}
+
+/// Revoke an access token on the server, so that it stops working even if it was copied from the credential store
+pub async fn revoke(client_credential: &Credential, server_url: &str, token: String) -> Result<()> {
+    let Some(registration) = load_registration(client_credential)? else {
+        bail!(
+            "The application isn't registered anymore, revoke the access token in your Mastodon settings"
+        );
+    };
+
+    create_client(server_url, None)?
+        .revoke_token(registration.client_id, registration.client_secret, token)
+        .await
+        .map_err(|e| eyre!("Failed to revoke access token: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use jiff::Timestamp;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::{fmt, time::Duration};
+
+const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RESET_HEADER: &str = "x-ratelimit-reset";
+
+/// Rate limit information returned by Mastodon in the `X-RateLimit-*` headers of each response
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Number of requests remaining in the current window
+    pub remaining: u64,
+    /// When the current window ends and the remaining count is reset
+    pub reset: Timestamp,
+}
+
+impl RateLimit {
+    /// Parse the rate limit headers of a response, returning `None` if they are missing or malformed
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let remaining = headers.get(REMAINING_HEADER)?.to_str().ok()?.parse().ok()?;
+        let reset = headers.get(RESET_HEADER)?.to_str().ok()?.parse().ok()?;
+
+        Some(Self { remaining, reset })
+    }
+}
+
+/// The server refused a request because the rate limit was exceeded (HTTP 429)
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimited {
+    /// How long the server asked us to wait, if it said so
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimited {
+    /// Read the delay from the `Retry-After` header (in seconds), or from the reset of the rate limit window
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs)
+            .or_else(|| {
+                let reset: Timestamp = headers.get(RESET_HEADER)?.to_str().ok()?.parse().ok()?;
+                Duration::try_from(Timestamp::now().duration_until(reset)).ok()
+            })
+            .filter(|wait| !wait.is_zero());
+
+        Self { retry_after }
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(wait) => write!(f, "Rate limited, retry in {}s", wait.as_secs()),
+            None => f.write_str("Rate limited"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
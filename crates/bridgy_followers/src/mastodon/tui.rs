@@ -3,35 +3,38 @@ use color_eyre::{
     eyre::{WrapErr, eyre},
     owo_colors::OwoColorize,
 };
-use dialoguer::{Input, Password, theme::ColorfulTheme};
+use dialoguer::{Input, Select, theme::ColorfulTheme};
 
 use keyring::CredentialBuilder;
-use megalodon::{Megalodon, mastodon::Mastodon, megalodon::AppInputOptions};
-use std::collections::HashSet;
-use tracing::info;
+use megalodon::{Megalodon, entities::Account, error::Error as MegalodonError, mastodon::Mastodon};
+use std::fmt;
+
+use super::{
+    client::MastodonClient,
+    oauth::{self, missing_scopes},
+};
+use tracing::{info, warn};
 
-use super::utils::create_client;
 use crate::{
     config::{Config, ConfigData},
     credentials,
-    mastodon::utils::get_account_following,
+    exit_code::AuthRequired,
+    interactive,
+    mastodon::utils::{get_account_following, server_url},
     println_or_info,
 };
 
 /// Prompt the user for the Mastodon server if not already set in config
 fn get_server(config: &mut Config) -> Result<String> {
     if let Some(server) = config.mastodon_server() {
-        Ok(server.to_string())
+        Ok(server_url(server))
     } else {
+        interactive::require_setting("mastodon_server")?;
         let input: String = Input::with_theme(&ColorfulTheme::default())
             .with_prompt("Mastodon server (e.g., mastodon.social or https://hachyderm.io)")
             .interact_text()?;
 
-        let server = if input.starts_with("http") {
-            input.clone()
-        } else {
-            format!("https://{input}")
-        };
+        let server = server_url(&input);
         config.mutate(|data| ConfigData {
             mastodon_server: Some(server.clone()),
             ..data
@@ -40,81 +43,165 @@ fn get_server(config: &mut Config) -> Result<String> {
     }
 }
 
-async fn register_application(server_url: String) -> Result<String> {
-    println!("Registering application...");
-    let client = create_client(&server_url, None)?;
-    let app_data = client
-        .register_app(
-            String::from("Bridgy Followers"),
-            &AppInputOptions {
-                scopes: Some(vec![
-                    String::from("read:accounts"),
-                    String::from("read:follows"),
-                    String::from("write:follows"),
-                ]),
-                redirect_uris: Some(String::from("urn:ietf:wg:oauth:2.0:oob")),
-                website: None,
-            },
-        )
-        .await
-        .wrap_err("Failed to register app")?;
-
-    let authorize_url = app_data.url.clone().unwrap_or_else(|| {
-        format!(
-            "{}/oauth/authorize?client_id={}&redirect_uri=urn:ietf:wg:oauth:2.0:oob&response_type=code&scope=read:accounts+read:follows",
-            server_url, app_data.client_id
-        )
-    });
+/// Why the stored access token can't be used
+enum TokenProblem {
+    /// The server doesn't accept the token anymore
+    Invalid,
+    /// The application was registered without some of the scopes we need, e.g. by an older version, so no token
+    /// issued to it can have them
+    MissingScopes(Vec<&'static str>),
+}
 
-    println!("\nPlease open this URL in your browser:\n{authorize_url}\n");
+impl fmt::Display for TokenProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenProblem::Invalid => write!(f, "is invalid, expired or was revoked"),
+            TokenProblem::MissingScopes(scopes) => {
+                write!(f, "is missing the {} scopes", scopes.join(", "))
+            }
+        }
+    }
+}
 
-    let auth_code: String = Password::with_theme(&ColorfulTheme::default())
-        .with_prompt("Enter the authorization code")
-        .interact()?;
+/// Check that the access token is still accepted by the server and has all the scopes we need. Returns why it can't
+/// be used, or `None` if it's valid.
+///
+/// Mastodon doesn't expose the scopes of an access token, only the ones its application was registered with. A token
+/// is never granted more than those, and we always request all the scopes we need, so the token is missing scopes
+/// when the application is.
+async fn verify_token(client: &MastodonClient) -> Result<Option<TokenProblem>> {
+    match client.verify_account_credentials().await {
+        Ok(response) => info!("Logged in to Mastodon as @{}", response.json().acct),
+        Err(MegalodonError::OwnError(own_error)) if matches!(own_error.status, Some(401 | 403)) => {
+            return Ok(Some(TokenProblem::Invalid));
+        }
+        Err(e) => return Err(eyre!("Failed to verify credentials: {}", e)),
+    }
 
-    println!("Getting access token...");
-    let token_data = client
-        .fetch_access_token(
-            app_data.client_id,
-            app_data.client_secret,
-            auth_code,
-            String::from("urn:ietf:wg:oauth:2.0:oob"),
-        )
+    let scopes = client
+        .application_scopes()
         .await
-        .map_err(|e| eyre!("Failed to fetch access token: {}", e))?;
+        .wrap_err("Failed to verify the scopes of the application")?;
+    let missing = scopes
+        .map(|scopes| missing_scopes(scopes.iter().map(String::as_str)))
+        .unwrap_or_default();
+    if missing.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(TokenProblem::MissingScopes(missing)))
+    }
+}
 
-    let access_token = token_data.access_token;
+/// What to do when the stored access token can't be used
+enum Reauthentication {
+    SameServer,
+    OtherServer,
+}
+
+fn prompt_reauthentication(server_url: &str, problem: &TokenProblem) -> Result<Reauthentication> {
+    let message = format!("The Mastodon access token for {server_url} {problem}");
+    if !interactive::is_interactive() {
+        return Err(AuthRequired(message))
+            .wrap_err("Run bridgy_followers interactively to log in to Mastodon again");
+    }
 
-    println!("✓ Authentication successful!");
+    warn!("{message}");
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(message)
+        .items(&[
+            format!("Log in again on {server_url}"),
+            "Use another Mastodon server".to_string(),
+        ])
+        .default(0)
+        .interact()?;
 
-    Ok(access_token)
+    Ok(match choice {
+        0 => Reauthentication::SameServer,
+        _ => Reauthentication::OtherServer,
+    })
 }
 
+/// Log in to Mastodon with the access token from the credential store, authorizing the application if there is none.
+/// The token is verified right away so that a revoked token is replaced before any command starts.
 pub async fn authenticate(
     credential_builder: &Box<CredentialBuilder>,
     config: &mut Config,
-) -> Result<Mastodon> {
-    let server_url = get_server(config)?;
-
-    let credentials = credentials::get_mastodon_access_token(credential_builder, &server_url)?;
-
-    let access_token = if let Ok(token) = credentials.get_password() {
-        token
-    } else {
-        let token = register_application(server_url.clone()).await?;
-        credentials.set_password(&token)?;
-        token
-    };
-
-    let client = create_client(&server_url, Some(access_token))?;
+) -> Result<MastodonClient> {
+    let mut register = false;
+    loop {
+        let server_url = get_server(config)?;
+
+        let credentials = credentials::get_mastodon_access_token(
+            credential_builder,
+            config.profile(),
+            &server_url,
+        )?;
+
+        // Only a missing token starts a login, a store that can't be read couldn't save the new token either
+        let stored_token = match credentials.get_password() {
+            Ok(token) if !register => Some(token),
+            Ok(_) | Err(keyring::Error::NoEntry) => None,
+            Err(e) => return Err(e).wrap_err("Failed to access credential store"),
+        };
+        let access_token = match stored_token {
+            Some(token) => token,
+            None => {
+                interactive::require_credential(&format!(
+                    "Mastodon access token for {server_url}"
+                ))?;
+                let client_credential = credentials::get_mastodon_client(
+                    credential_builder,
+                    config.profile(),
+                    &server_url,
+                )?;
+                let token = oauth::login(
+                    &client_credential,
+                    &server_url,
+                    config.mastodon_oauth_callback_port(),
+                )
+                .await?;
+                if let Err(e) = credentials.set_password(&token) {
+                    // Don't leave a token that nothing knows about on the server
+                    if let Err(e) = oauth::revoke(&client_credential, &server_url, token).await {
+                        warn!("Failed to revoke the new Mastodon access token: {e}");
+                    }
+                    return Err(e).wrap_err("Failed to save the Mastodon access token");
+                }
+                token
+            }
+        };
 
-    // TODO: We should use verify_account_credentials here to ensure the token is valid and prompt for
-    // re-authentication or server-change if not.
+        let client = MastodonClient::new(&server_url, access_token)?;
+        let Some(problem) = verify_token(&client).await? else {
+            return Ok(client);
+        };
 
-    Ok(client)
+        match prompt_reauthentication(&server_url, &problem)? {
+            Reauthentication::SameServer => {
+                // The application must be registered again with the scopes it's missing
+                if let TokenProblem::MissingScopes(_) = problem {
+                    let client_credential = credentials::get_mastodon_client(
+                        credential_builder,
+                        config.profile(),
+                        &server_url,
+                    )?;
+                    oauth::forget_registration(&client_credential)?;
+                }
+                register = true;
+            }
+            Reauthentication::OtherServer => {
+                config.mutate(|data| ConfigData {
+                    mastodon_server: None,
+                    ..data
+                })?;
+                register = false;
+            }
+        }
+    }
 }
 
-pub async fn get_following(client: &Mastodon, quiet: bool) -> Result<HashSet<String>> {
+/// Get all the accounts the authenticated user follows
+pub async fn get_following_accounts(client: &Mastodon, quiet: bool) -> Result<Vec<Account>> {
     info!("Fetching current user...");
 
     let account_response = client
@@ -137,7 +224,5 @@ pub async fn get_following(client: &Mastodon, quiet: bool) -> Result<HashSet<Str
         following.len().yellow()
     );
 
-    let following: HashSet<String> = following.into_iter().map(|account| account.acct).collect();
-
     Ok(following)
 }
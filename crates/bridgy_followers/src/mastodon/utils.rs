@@ -1,10 +1,32 @@
-use color_eyre::{Result, eyre::WrapErr};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
 use megalodon::{
-    Megalodon, entities::Account, mastodon::Mastodon, megalodon::AccountFollowersInputOptions,
+    Megalodon,
+    entities::{Account, Relationship},
+    error::Error as MegalodonError,
+    mastodon::Mastodon,
+    megalodon::AccountFollowersInputOptions,
+    response::Response,
 };
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
 use tracing::{info, instrument};
 
-const USER_AGENT: &str = "bridgy_followers";
+use super::{client::MastodonClient, rate_limit::RateLimit};
+use crate::config::FollowOptions;
+
+pub(super) const USER_AGENT: &str = "bridgy_followers";
+
+/// URL of a Mastodon server given with or without its scheme, e.g. `mastodon.social` or `https://hachyderm.io`
+pub fn server_url(server: &str) -> String {
+    if server.starts_with("http") {
+        server.to_string()
+    } else {
+        format!("https://{server}")
+    }
+}
 
 pub fn create_client(base_url: &str, access_token: Option<String>) -> Result<Mastodon> {
     Mastodon::new(
@@ -15,31 +37,273 @@ pub fn create_client(base_url: &str, access_token: Option<String>) -> Result<Mas
     .wrap_err_with(|| format!("Failed to create Mastodon client at {base_url}"))
 }
 
-/// Follow an account on Mastodon by its handle (e.g., "user@bsky.brid.gy")
+/// An account that was just followed
+#[derive(Debug, Clone)]
+pub struct FollowedAccount {
+    /// Id of the account on the Mastodon server
+    pub account_id: String,
+    /// Rate limit reported by the server after the follow, if any
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Look up the id of an account to follow by its handle (e.g., "user@bsky.brid.gy"), with the rate limit reported by
+/// the server if any
 #[instrument(skip(client))]
-pub async fn follow_account(client: &Mastodon, account_handle: &str) -> Result<()> {
+pub async fn lookup_account_id(
+    client: &MastodonClient,
+    account_handle: &str,
+) -> Result<(String, Option<RateLimit>)> {
     let account_response = client
         .lookup_account(account_handle.to_string())
         .await
         .wrap_err_with(|| format!("Failed to lookup account {}", account_handle))?;
 
-    let account = account_response.json();
+    let rate_limit = RateLimit::from_headers(&account_response.header);
+    Ok((account_response.json().id, rate_limit))
+}
 
-    client
-        .follow_account(account.id.clone(), None)
+/// Follow an account on Mastodon by its id, the handle is only used in messages
+#[instrument(skip(client))]
+pub async fn follow_account(
+    client: &MastodonClient,
+    account_handle: &str,
+    account_id: &str,
+    options: &FollowOptions,
+) -> Result<FollowedAccount> {
+    let follow_headers = client
+        .follow_account_with_options(
+            account_id,
+            options.reblogs,
+            options.notify,
+            &options.languages,
+        )
         .await
         .wrap_err_with(|| {
             format!(
                 "Failed to follow account {} (id: {})",
-                account_handle, account.id
+                account_handle, account_id
             )
         })?;
 
-    info!(%account.id, "Successfully followed {}", account_handle);
+    info!(%account_id, "Successfully followed {}", account_handle);
+
+    Ok(FollowedAccount {
+        account_id: account_id.to_string(),
+        rate_limit: RateLimit::from_headers(&follow_headers),
+    })
+}
+
+/// Unfollow an account on Mastodon by its id
+#[instrument(skip(client))]
+pub async fn unfollow_account(client: &Mastodon, account_id: &str) -> Result<()> {
+    client
+        .unfollow_account(account_id.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to unfollow account {account_id}"))?;
+
+    info!("Successfully unfollowed {}", account_id);
+
+    Ok(())
+}
+
+/// Find an account on Mastodon by its handle (e.g., "user@bsky.brid.gy")
+#[instrument(skip(client))]
+pub async fn lookup_account(client: &Mastodon, account_handle: &str) -> Result<Account> {
+    let account = client
+        .lookup_account(account_handle.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to lookup account {}", account_handle))?
+        .json();
+
+    Ok(account)
+}
+
+/// Find an account on Mastodon by its handle, `None` if the server doesn't know it or can't resolve it (e.g. a Bluesky
+/// account that isn't bridged)
+#[instrument(skip(client))]
+pub async fn find_account(client: &Mastodon, account_handle: &str) -> Result<Option<Account>> {
+    match client.lookup_account(account_handle.to_string()).await {
+        Ok(response) => Ok(Some(response.json())),
+        Err(MegalodonError::OwnError(own_error)) if own_error.status == Some(404) => Ok(None),
+        Err(e) => Err(e).wrap_err_with(|| format!("Failed to lookup account {account_handle}")),
+    }
+}
+
+/// Get the relationship between the authenticated user and an account
+#[instrument(skip(client))]
+pub async fn get_relationship(client: &Mastodon, account_id: &str) -> Result<Relationship> {
+    client
+        .get_relationships(vec![account_id.to_string()])
+        .await
+        .wrap_err_with(|| format!("Failed to get relationship with account {account_id}"))?
+        .json()
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("No relationship returned for account {account_id}"))
+}
+
+/// Block an account on Mastodon by its id
+#[instrument(skip(client))]
+pub async fn block_account(client: &Mastodon, account_id: &str) -> Result<()> {
+    client
+        .block_account(account_id.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to block account {account_id}"))?;
+
+    info!("Successfully blocked {}", account_id);
+
+    Ok(())
+}
+
+/// Unblock an account on Mastodon by its id
+#[instrument(skip(client))]
+pub async fn unblock_account(client: &Mastodon, account_id: &str) -> Result<()> {
+    client
+        .unblock_account(account_id.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to unblock account {account_id}"))?;
+
+    info!("Successfully unblocked {}", account_id);
+
+    Ok(())
+}
+
+/// Mute an account and its notifications on Mastodon by its id
+#[instrument(skip(client))]
+pub async fn mute_account(client: &Mastodon, account_id: &str) -> Result<()> {
+    client
+        .mute_account(account_id.to_string(), true)
+        .await
+        .wrap_err_with(|| format!("Failed to mute account {account_id}"))?;
+
+    info!("Successfully muted {}", account_id);
+
+    Ok(())
+}
+
+/// Unmute an account on Mastodon by its id
+#[instrument(skip(client))]
+pub async fn unmute_account(client: &Mastodon, account_id: &str) -> Result<()> {
+    client
+        .unmute_account(account_id.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to unmute account {account_id}"))?;
+
+    info!("Successfully unmuted {}", account_id);
 
     Ok(())
 }
 
+/// Blocks, mutes and domain blocks of the authenticated user
+#[derive(Debug, Default)]
+pub struct Moderation {
+    /// Account addresses of the blocked accounts
+    pub blocked: HashSet<String>,
+    /// Account addresses of the muted accounts
+    pub muted: HashSet<String>,
+    pub blocked_domains: HashSet<String>,
+}
+
+impl Moderation {
+    /// Whether accounts of a domain are blocked, by blocking the domain itself or one of its parent domains (e.g.
+    /// `brid.gy` for `bsky.brid.gy`) as Mastodon does
+    pub fn blocks_domain(&self, domain: &str) -> bool {
+        let domain = domain.to_lowercase();
+        let mut parent = domain.as_str();
+        loop {
+            if self.blocked_domains.contains(parent) {
+                return true;
+            }
+            match parent.split_once('.') {
+                Some((_, rest)) if rest.contains('.') => parent = rest,
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Get the blocks, mutes and domain blocks of the authenticated user, `None` if the access token isn't allowed to read
+/// them (it was created without the `read:blocks` or `read:mutes` scope)
+#[instrument(skip(client))]
+pub async fn get_moderation(client: &Mastodon) -> Result<Option<Moderation>> {
+    let Some(blocked) = get_all_pages(client, client.get_blocks(None).await, "blocks").await?
+    else {
+        return Ok(None);
+    };
+    let Some(muted) = get_all_pages(client, client.get_mutes(None).await, "mutes").await? else {
+        return Ok(None);
+    };
+    let Some(blocked_domains) = get_all_pages(
+        client,
+        client.get_domain_blocks(None).await,
+        "domain blocks",
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let moderation = Moderation {
+        blocked: blocked
+            .into_iter()
+            .map(|account| account.acct.to_lowercase())
+            .collect(),
+        muted: muted
+            .into_iter()
+            .map(|account| account.acct.to_lowercase())
+            .collect(),
+        blocked_domains: blocked_domains
+            .into_iter()
+            .map(|domain| domain.to_lowercase())
+            .collect(),
+    };
+
+    info!(
+        blocked = moderation.blocked.len(),
+        muted = moderation.muted.len(),
+        blocked_domains = moderation.blocked_domains.len(),
+        "Fetched moderation"
+    );
+    Ok(Some(moderation))
+}
+
+/// Collect the items of a paginated endpoint from its first page, following the `Link` header to the next pages.
+/// Returns `None` if the access token isn't allowed to read the endpoint.
+async fn get_all_pages<T: Clone + DeserializeOwned>(
+    client: &Mastodon,
+    first_page: Result<Response<Vec<T>>, MegalodonError>,
+    name: &str,
+) -> Result<Option<Vec<T>>> {
+    let mut response = match first_page {
+        Ok(response) => response,
+        Err(MegalodonError::OwnError(own_error)) if own_error.status == Some(403) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e).wrap_err_with(|| format!("Failed to get {name}")),
+    };
+
+    let mut items = Vec::new();
+    loop {
+        let mut page = response.json();
+        if page.is_empty() {
+            break;
+        }
+        items.append(&mut page);
+
+        match response.next_uri().wrap_err("Failed to get next page")? {
+            Some(next) => {
+                response = client
+                    .get_linked_response(next)
+                    .await
+                    .wrap_err_with(|| format!("Failed to get continuation of {name}"))?;
+            }
+            None => break,
+        }
+    }
+
+    Ok(Some(items))
+}
+
 /// Get all accounts which the given acount is following.
 #[instrument(skip(client))]
 pub async fn get_account_following(client: &Mastodon, user_id: String) -> Result<Vec<Account>> {
@@ -80,3 +344,56 @@ pub async fn get_account_following(client: &Mastodon, user_id: String) -> Result
     info!("Fetched {} following accounts", following.len());
     Ok(following)
 }
+
+/// Get the id of the list with the given title, creating it if it doesn't exist
+#[instrument(skip(client))]
+pub async fn get_or_create_list(client: &Mastodon, title: &str) -> Result<String> {
+    let lists = client
+        .get_lists()
+        .await
+        .wrap_err("Failed to get lists")?
+        .json();
+
+    if let Some(list) = lists.into_iter().find(|list| list.title == title) {
+        return Ok(list.id);
+    }
+
+    let list = client
+        .create_list(title.to_string())
+        .await
+        .wrap_err_with(|| format!("Failed to create list '{title}'"))?
+        .json();
+
+    info!(%list.id, "Created list '{title}'");
+
+    Ok(list.id)
+}
+
+/// Get the ids of all the accounts in a list
+#[instrument(skip(client))]
+pub async fn get_list_account_ids(client: &Mastodon, list_id: &str) -> Result<Vec<String>> {
+    let accounts = get_all_pages(
+        client,
+        client.get_accounts_in_list(list_id.to_string(), None).await,
+        "accounts in list",
+    )
+    .await?
+    .ok_or_else(|| eyre!("Not allowed to get the accounts in list {list_id}"))?;
+
+    Ok(accounts.into_iter().map(|account| account.id).collect())
+}
+
+/// Add accounts to a list, the accounts must already be followed
+#[instrument(skip(client))]
+pub async fn add_accounts_to_list(
+    client: &Mastodon,
+    list_id: &str,
+    account_ids: Vec<String>,
+) -> Result<()> {
+    client
+        .add_accounts_to_list(list_id.to_string(), account_ids)
+        .await
+        .wrap_err_with(|| format!("Failed to add accounts to list {list_id}"))?;
+
+    Ok(())
+}
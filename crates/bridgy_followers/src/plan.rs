@@ -0,0 +1,62 @@
+use crate::follower_status::{BridgedFollower, FollowerStatus};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail},
+};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A reviewed set of follows computed by `sync --plan` that can be executed later by `apply`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FollowPlan {
+    /// When the statuses in this plan were computed
+    pub created_at: Timestamp,
+    /// The Mastodon server the plan was computed against
+    pub mastodon_server: String,
+    pub followers: Vec<BridgedFollower>,
+}
+
+impl FollowPlan {
+    pub fn new(mastodon_server: String, followers: Vec<BridgedFollower>) -> Self {
+        Self {
+            created_at: Timestamp::now(),
+            mastodon_server,
+            followers,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read plan {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Failed to parse plan {}", path.display()))
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+            .wrap_err_with(|| format!("Failed to write plan {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Fail if the plan was created more than `max_age` ago, as the statuses it contains may be stale
+    pub fn ensure_fresh(&self, max_age: SignedDuration) -> Result<()> {
+        let age = Timestamp::now().duration_since(self.created_at);
+        if age > max_age {
+            bail!(
+                "Plan was created at {} which is older than the maximum allowed age of {:#}, run `sync --plan` again",
+                self.created_at,
+                max_age
+            );
+        }
+        Ok(())
+    }
+
+    /// The followers of the plan that should be followed when applying it
+    pub fn ready_to_follow(&self) -> impl Iterator<Item = &BridgedFollower> {
+        self.followers
+            .iter()
+            .filter(|f| f.status == FollowerStatus::ReadyToFollow)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::follower_status::{BridgedFollower, FollowerStatus};
+use clap::ValueEnum;
+use color_eyre::{Report, Result, eyre::bail};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Output format of a report
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    /// A JSON array of accounts
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// A Markdown table
+    Markdown,
+    /// A plain text table
+    Table,
+}
+
+/// A single account of a report, flattened so that it's easy to consume from scripts
+#[derive(Debug, Serialize)]
+struct ReportEntry<'a> {
+    handle: &'a str,
+    did: &'a str,
+    mastodon_acct: &'a str,
+    status: String,
+    reason: Option<String>,
+    description: String,
+}
+
+impl<'a> TryFrom<&'a BridgedFollower> for ReportEntry<'a> {
+    type Error = Report;
+
+    fn try_from(follower: &'a BridgedFollower) -> Result<Self> {
+        let (status, reason) = status_names(&follower.status)?;
+        Ok(Self {
+            handle: follower.handle.as_str(),
+            did: follower.did.as_str(),
+            mastodon_acct: &follower.mastodon_acct,
+            status,
+            reason,
+            description: follower.status.to_string(),
+        })
+    }
+}
+
+/// The names of a status and of its reason, taken from their serialization so that they are the same as in plan and
+/// state files: `"ready_to_follow"`, or `{"not_bridged": "blocks_bridge"}` for a status with a reason
+fn status_names(status: &FollowerStatus) -> Result<(String, Option<String>)> {
+    match serde_json::to_value(status)? {
+        Value::String(status) => Ok((status, None)),
+        Value::Object(object) => match object.into_iter().next() {
+            Some((status, Value::String(reason))) => Ok((status, Some(reason))),
+            _ => bail!("Unexpected serialization of status '{status}'"),
+        },
+        _ => bail!("Unexpected serialization of status '{status}'"),
+    }
+}
+
+/// Render the statuses of all the accounts in the given format
+pub fn statuses_to_report(statuses: &[BridgedFollower], format: ReportFormat) -> Result<String> {
+    let mut entries = statuses
+        .iter()
+        .map(ReportEntry::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by(|a, b| a.handle.cmp(b.handle));
+
+    let mut report = String::new();
+    match format {
+        ReportFormat::Json => {
+            report = serde_json::to_string_pretty(&entries)?;
+            report.push('\n');
+        }
+        ReportFormat::Ndjson => {
+            for entry in &entries {
+                writeln!(report, "{}", serde_json::to_string(entry)?)?;
+            }
+        }
+        ReportFormat::Markdown => {
+            writeln!(report, "| Handle | DID | Mastodon account | Status |")?;
+            writeln!(report, "| --- | --- | --- | --- |")?;
+            for entry in &entries {
+                writeln!(
+                    report,
+                    "| {} | `{}` | @{} | {} |",
+                    entry.handle, entry.did, entry.mastodon_acct, entry.description
+                )?;
+            }
+        }
+        ReportFormat::Table => {
+            let headers = ["HANDLE", "DID", "MASTODON ACCOUNT", "STATUS"];
+            let rows: Vec<[&str; 4]> = entries
+                .iter()
+                .map(|entry| {
+                    [
+                        entry.handle,
+                        entry.did,
+                        entry.mastodon_acct,
+                        entry.description.as_str(),
+                    ]
+                })
+                .collect();
+
+            let mut widths = headers.map(str::len);
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+
+            for row in std::iter::once(&headers).chain(&rows) {
+                let line = row
+                    .iter()
+                    .zip(widths)
+                    .map(|(cell, width)| format!("{cell:<width$}"))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                writeln!(report, "{}", line.trim_end())?;
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,581 @@
+use crate::{
+    config::Config,
+    fediverse_status::BridgedFediverseAccount,
+    follower_status::{BridgedFollower, FollowerStatus},
+    utils::write_atomically,
+};
+use atrium_api::types::string::{Did, Handle};
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, bail},
+};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// Version of the state file format, bumped when a migration is needed
+const STATE_VERSION: u32 = 1;
+
+/// Number of runs kept in the state, older runs are dropped so that `watch` doesn't grow the file forever
+const MAX_RUNS: usize = 1000;
+
+pub type RunId = u64;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StateData {
+    pub version: u32,
+    #[serde(default)]
+    pub runs: Vec<Run>,
+    /// History of each account we ever saw, keyed by DID
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AccountHistory>,
+    /// Blocks and mutes applied on Mastodon by `moderation sync`, keyed by DID
+    #[serde(default)]
+    pub moderation: BTreeMap<String, AppliedModeration>,
+    /// Fediverse accounts followed on Bluesky by `sync --direction mastodon-to-bluesky`, keyed by Mastodon account
+    /// address
+    #[serde(default)]
+    pub bluesky_follows: BTreeMap<String, BlueskyFollowHistory>,
+}
+
+impl Default for StateData {
+    fn default() -> Self {
+        Self {
+            version: STATE_VERSION,
+            runs: Vec::new(),
+            accounts: BTreeMap::new(),
+            moderation: BTreeMap::new(),
+            bluesky_follows: BTreeMap::new(),
+        }
+    }
+}
+
+/// A single execution of a command that computed statuses or followed accounts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Run {
+    pub id: RunId,
+    pub command: String,
+    pub started_at: Timestamp,
+    pub finished_at: Option<Timestamp>,
+    /// Number of accounts seen in this run
+    #[serde(default)]
+    pub accounts_seen: usize,
+    #[serde(default)]
+    pub followed: usize,
+    #[serde(default)]
+    pub follow_failures: usize,
+    #[serde(default)]
+    pub unfollowed: usize,
+    /// Number of blocks and mutes applied or lifted on Mastodon
+    #[serde(default)]
+    pub moderation_changes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountHistory {
+    /// Last known Bluesky handle of the account
+    pub handle: Handle,
+    /// Last known Mastodon account address of the account
+    pub mastodon_acct: String,
+    /// Id of the bridged account on the Mastodon server, it doesn't change when the handle changes
+    #[serde(default)]
+    pub mastodon_id: Option<String>,
+    /// Status changes, only recorded when the status differs from the previous one
+    #[serde(default)]
+    pub statuses: Vec<StatusChange>,
+    #[serde(default)]
+    pub follows: Vec<FollowAction>,
+}
+
+impl AccountHistory {
+    /// The status of the account as it was known at a given point in time
+    pub fn status_at(&self, at: Timestamp) -> Option<&StatusChange> {
+        self.statuses.iter().rev().find(|change| change.at <= at)
+    }
+
+    pub fn current_status(&self) -> Option<&StatusChange> {
+        self.statuses.last()
+    }
+}
+
+/// History of a fediverse account followed on Bluesky through its bridged account
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlueskyFollowHistory {
+    /// Last known handle of the bridged account on Bluesky
+    pub bluesky_handle: String,
+    /// DID of the bridged account, only known if it exists on Bluesky
+    #[serde(default)]
+    pub did: Option<Did>,
+    #[serde(default)]
+    pub follows: Vec<FollowAction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatusChange {
+    pub run: RunId,
+    pub at: Timestamp,
+    pub status: FollowerStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FollowAction {
+    pub run: RunId,
+    pub at: Timestamp,
+    pub mastodon_acct: String,
+    pub outcome: FollowOutcome,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FollowOutcome {
+    Followed,
+    Failed { error: String },
+    Unfollowed,
+}
+
+/// Moderation mirrored from Bluesky to the bridged account on Mastodon
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppliedModeration {
+    /// Last known Bluesky handle of the account
+    pub handle: Handle,
+    pub mastodon_acct: String,
+    /// Id of the bridged account on the Mastodon server
+    pub mastodon_id: String,
+    /// When the account was blocked on Mastodon, if it still is
+    pub blocked_at: Option<Timestamp>,
+    /// When the account was muted on Mastodon, if it still is
+    pub muted_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModerationAction {
+    Block,
+    Unblock,
+    Mute,
+    Unmute,
+}
+
+impl fmt::Display for ModerationAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationAction::Block => write!(f, "Block"),
+            ModerationAction::Unblock => write!(f, "Unblock"),
+            ModerationAction::Mute => write!(f, "Mute"),
+            ModerationAction::Unmute => write!(f, "Unmute"),
+        }
+    }
+}
+
+/// Local store recording sync runs and the history of each account
+pub struct State {
+    data: StateData,
+    path: PathBuf,
+}
+
+impl State {
+    /// Load the state of the profile selected in the given configuration, stored next to the configuration file
+    pub fn for_config(config: &Config) -> Result<Self> {
+        Self::from_file(&state_path(config))
+    }
+
+    /// Load the state from a file, starting from an empty state if it doesn't exist. A file that can't be parsed is
+    /// set aside with a `.corrupt` extension so that commands can still run.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = match fs::read_to_string(path) {
+            Ok(contents) => {
+                let data: StateData = match serde_json::from_str(&contents) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        let corrupt_path = path.with_extension("json.corrupt");
+                        fs::rename(path, &corrupt_path).wrap_err_with(|| {
+                            format!("Failed to move corrupt state {}", path.display())
+                        })?;
+                        warn!(
+                            "State {} is corrupt ({e}), moved it to {} and starting from an empty state",
+                            path.display(),
+                            corrupt_path.display()
+                        );
+                        StateData::default()
+                    }
+                };
+                if data.version > STATE_VERSION {
+                    bail!(
+                        "State file {} has version {} but this version of bridgy_followers only supports up to {}",
+                        path.display(),
+                        data.version,
+                        STATE_VERSION
+                    );
+                }
+                data
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => StateData::default(),
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        Ok(State {
+            data,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn data(&self) -> &StateData {
+        &self.data
+    }
+
+    /// Find an account history by DID or by any handle it's currently known as
+    pub fn find_account(&self, handle_or_did: &str) -> Option<(&str, &AccountHistory)> {
+        let clean = handle_or_did.trim_start_matches('@').to_lowercase();
+        self.data
+            .accounts
+            .iter()
+            .find(|(did, account)| {
+                **did == clean
+                    || account.handle.as_str().to_lowercase() == clean
+                    || account.mastodon_acct == clean
+            })
+            .map(|(did, account)| (did.as_str(), account))
+    }
+
+    /// The id of the bridged account of a DID on the Mastodon server, if it was ever seen
+    pub fn mastodon_id(&self, did: &Did) -> Option<&str> {
+        self.data
+            .accounts
+            .get(did.as_str())
+            .and_then(|account| account.mastodon_id.as_deref())
+    }
+
+    /// Record the id of the bridged account of a follower on the Mastodon server
+    pub fn record_mastodon_id(&mut self, follower: &BridgedFollower, mastodon_id: &str) {
+        let account = self.account_mut(&follower.did, &follower.handle, &follower.mastodon_acct);
+        account.mastodon_id = Some(mastodon_id.to_string());
+    }
+
+    /// Find an account history by the id of its bridged account on the Mastodon server
+    pub fn find_by_mastodon_id(&self, mastodon_id: &str) -> Option<(&str, &AccountHistory)> {
+        self.data
+            .accounts
+            .iter()
+            .find(|(_, account)| account.mastodon_id.as_deref() == Some(mastodon_id))
+            .map(|(did, account)| (did.as_str(), account))
+    }
+
+    /// Whether the current follow of a Mastodon account was created by this tool, i.e. the last successful action
+    /// recorded for it is a follow. The account is matched by id so that follows made under a previous handle count.
+    pub fn followed_by_tool(&self, mastodon_acct: &str, mastodon_id: &str) -> bool {
+        self.data.accounts.values().any(|account| {
+            let same_account = account.mastodon_id.as_deref() == Some(mastodon_id);
+            account
+                .follows
+                .iter()
+                .rev()
+                .filter(|action| same_account || action.mastodon_acct == mastodon_acct)
+                .find(|action| !matches!(action.outcome, FollowOutcome::Failed { .. }))
+                .is_some_and(|action| action.outcome == FollowOutcome::Followed)
+        })
+    }
+
+    pub fn start_run(&mut self, command: &str) -> RunId {
+        let id = self.data.runs.last().map_or(1, |run| run.id + 1);
+        self.data.runs.push(Run {
+            id,
+            command: command.to_string(),
+            started_at: Timestamp::now(),
+            finished_at: None,
+            accounts_seen: 0,
+            followed: 0,
+            follow_failures: 0,
+            unfollowed: 0,
+            moderation_changes: 0,
+        });
+        let excess = self.data.runs.len().saturating_sub(MAX_RUNS);
+        self.data.runs.drain(..excess);
+        id
+    }
+
+    pub fn finish_run(&mut self, run: RunId) {
+        if let Some(run) = self.run_mut(run) {
+            run.finished_at = Some(Timestamp::now());
+        }
+    }
+
+    fn run_mut(&mut self, run: RunId) -> Option<&mut Run> {
+        self.data.runs.iter_mut().rev().find(|r| r.id == run)
+    }
+
+    fn account_mut(
+        &mut self,
+        did: &Did,
+        handle: &Handle,
+        mastodon_acct: &str,
+    ) -> &mut AccountHistory {
+        let account = self
+            .data
+            .accounts
+            .entry(did.as_str().to_string())
+            .or_insert_with(|| AccountHistory {
+                handle: handle.clone(),
+                mastodon_acct: mastodon_acct.to_string(),
+                mastodon_id: None,
+                statuses: Vec::new(),
+                follows: Vec::new(),
+            });
+        account.handle = handle.clone();
+        account.mastodon_acct = mastodon_acct.to_string();
+        account
+    }
+
+    /// Record the statuses computed during a run, keeping only the transitions
+    pub fn record_statuses(&mut self, run: RunId, statuses: &[BridgedFollower]) {
+        let now = Timestamp::now();
+        for follower in statuses {
+            let account =
+                self.account_mut(&follower.did, &follower.handle, &follower.mastodon_acct);
+            let changed = account
+                .current_status()
+                .is_none_or(|last| last.status != follower.status);
+            if changed {
+                account.statuses.push(StatusChange {
+                    run,
+                    at: now,
+                    status: follower.status.clone(),
+                });
+            }
+        }
+
+        if let Some(run) = self.run_mut(run) {
+            run.accounts_seen += statuses.len();
+        }
+    }
+
+    /// Record the outcome of following an account
+    pub fn record_follow(
+        &mut self,
+        run: RunId,
+        follower: &BridgedFollower,
+        outcome: FollowOutcome,
+    ) {
+        let now = Timestamp::now();
+        let succeeded = outcome == FollowOutcome::Followed;
+
+        let account = self.account_mut(&follower.did, &follower.handle, &follower.mastodon_acct);
+        account.follows.push(FollowAction {
+            run,
+            at: now,
+            mastodon_acct: follower.mastodon_acct.clone(),
+            outcome,
+        });
+        if succeeded
+            && account
+                .current_status()
+                .is_none_or(|last| last.status != FollowerStatus::AlreadyFollowedOnMastodon)
+        {
+            account.statuses.push(StatusChange {
+                run,
+                at: now,
+                status: FollowerStatus::AlreadyFollowedOnMastodon,
+            });
+        }
+
+        if let Some(run) = self.run_mut(run) {
+            if succeeded {
+                run.followed += 1;
+            } else {
+                run.follow_failures += 1;
+            }
+        }
+    }
+
+    /// Record the outcome of following a fediverse account on Bluesky
+    pub fn record_bluesky_follow(
+        &mut self,
+        run: RunId,
+        account: &BridgedFediverseAccount,
+        outcome: FollowOutcome,
+    ) {
+        let succeeded = outcome == FollowOutcome::Followed;
+
+        let history = self
+            .data
+            .bluesky_follows
+            .entry(account.mastodon_acct.clone())
+            .or_insert_with(|| BlueskyFollowHistory {
+                bluesky_handle: account.bluesky_handle.clone(),
+                did: None,
+                follows: Vec::new(),
+            });
+        history.bluesky_handle = account.bluesky_handle.clone();
+        if account.did.is_some() {
+            history.did = account.did.clone();
+        }
+        history.follows.push(FollowAction {
+            run,
+            at: Timestamp::now(),
+            mastodon_acct: account.mastodon_acct.clone(),
+            outcome,
+        });
+
+        if let Some(run) = self.run_mut(run) {
+            if succeeded {
+                run.followed += 1;
+            } else {
+                run.follow_failures += 1;
+            }
+        }
+    }
+
+    /// Record how many accounts a run saw, for runs that don't record statuses
+    pub fn record_accounts_seen(&mut self, run: RunId, count: usize) {
+        if let Some(run) = self.run_mut(run) {
+            run.accounts_seen += count;
+        }
+    }
+
+    /// Record that a Mastodon account was unfollowed, if we know about it
+    pub fn record_unfollow(&mut self, run: RunId, mastodon_acct: &str) {
+        let now = Timestamp::now();
+        let account = self.data.accounts.values_mut().find(|account| {
+            account.mastodon_acct == mastodon_acct
+                || account
+                    .follows
+                    .iter()
+                    .any(|action| action.mastodon_acct == mastodon_acct)
+        });
+
+        if let Some(account) = account {
+            account.follows.push(FollowAction {
+                run,
+                at: now,
+                mastodon_acct: mastodon_acct.to_string(),
+                outcome: FollowOutcome::Unfollowed,
+            });
+        }
+
+        if let Some(run) = self.run_mut(run) {
+            run.unfollowed += 1;
+        }
+    }
+
+    /// Record a block or mute applied or lifted on Mastodon. Accounts are forgotten once nothing applies to them
+    /// anymore.
+    pub fn record_moderation(
+        &mut self,
+        run: RunId,
+        did: &Did,
+        handle: &Handle,
+        mastodon_acct: &str,
+        mastodon_id: &str,
+        action: ModerationAction,
+    ) {
+        let now = Timestamp::now();
+        let applied = self
+            .data
+            .moderation
+            .entry(did.as_str().to_string())
+            .or_insert_with(|| AppliedModeration {
+                handle: handle.clone(),
+                mastodon_acct: mastodon_acct.to_string(),
+                mastodon_id: mastodon_id.to_string(),
+                blocked_at: None,
+                muted_at: None,
+            });
+        applied.handle = handle.clone();
+
+        match action {
+            ModerationAction::Block => applied.blocked_at = Some(now),
+            ModerationAction::Unblock => applied.blocked_at = None,
+            ModerationAction::Mute => applied.muted_at = Some(now),
+            ModerationAction::Unmute => applied.muted_at = None,
+        }
+
+        if applied.blocked_at.is_none() && applied.muted_at.is_none() {
+            self.data.moderation.remove(did.as_str());
+        }
+
+        if let Some(run) = self.run_mut(run) {
+            run.moderation_changes += 1;
+        }
+    }
+
+    /// Write the state back to its file, atomically as it's saved after each follow
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.data)?;
+        write_atomically(&self.path, json.as_bytes())
+            .wrap_err_with(|| format!("Failed to write state {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Get the path of the state file of a profile, stored next to the configuration file
+pub fn state_path(config: &Config) -> PathBuf {
+    config.sibling_path("state.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state file path in a directory of its own, removed before the test
+    fn state_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bridgy_followers_state_{}_{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("config.state.json")
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = state_file("round_trip");
+        let mut state = State::from_file(&path).unwrap();
+        assert!(state.data().runs.is_empty());
+
+        let run = state.start_run("sync");
+        state.record_accounts_seen(run, 3);
+        state.finish_run(run);
+        state.save().unwrap();
+
+        let loaded = State::from_file(&path).unwrap();
+        let runs = &loaded.data().runs;
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].id, run);
+        assert_eq!(runs[0].command, "sync");
+        assert_eq!(runs[0].accounts_seen, 3);
+        assert!(runs[0].finished_at.is_some());
+        // The temporary file is renamed over the state
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn corrupt_state_is_set_aside() {
+        let path = state_file("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, r#"{"version": 1, "runs": [{"id": 1, "comm"#).unwrap();
+
+        let state = State::from_file(&path).unwrap();
+        assert!(state.data().runs.is_empty());
+        assert!(!path.exists());
+        assert!(path.with_extension("json.corrupt").exists());
+    }
+
+    #[test]
+    fn old_runs_are_dropped() {
+        let path = state_file("max_runs");
+        let mut state = State::from_file(&path).unwrap();
+        for _ in 0..MAX_RUNS + 5 {
+            state.start_run("watch");
+        }
+
+        let runs = &state.data().runs;
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert_eq!(runs[0].id, 6);
+        // Ids keep increasing after old runs are dropped
+        assert_eq!(state.start_run("watch"), MAX_RUNS as RunId + 6);
+    }
+}
@@ -1,9 +1,102 @@
-use atrium_api::types::string::Handle;
+use atrium_api::types::string::{Did, Handle};
+use color_eyre::{Result, eyre::eyre};
+use jiff::{Span, Timestamp, Zoned, civil, tz::TimeZone};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
 
 pub const BRIDGY_ACTIVITY_PUB_URL: &str = "https://fed.brid.gy";
 
-const BRIDGY_MASTODON_DOMAIN: &str = "bsky.brid.gy";
+pub const BRIDGY_MASTODON_DOMAIN: &str = "bsky.brid.gy";
+
+const BRIDGY_BLUESKY_DOMAIN: &str = "ap.brid.gy";
 
 pub fn bluesky_handle_to_mastodon(handle: &Handle) -> String {
     format!("{}@{}", handle.as_str(), BRIDGY_MASTODON_DOMAIN).to_lowercase()
 }
+
+/// Get the Bluesky handle of a Mastodon account address if it's an account bridged from Bluesky
+/// (e.g. "user.bsky.social@bsky.brid.gy" gives "user.bsky.social")
+pub fn mastodon_acct_to_bluesky_handle(acct: &str) -> Option<String> {
+    let (username, domain) = acct.trim_start_matches('@').rsplit_once('@')?;
+    domain
+        .eq_ignore_ascii_case(BRIDGY_MASTODON_DOMAIN)
+        .then(|| username.to_lowercase())
+}
+
+/// Find the DID in the URL of a bridged account, either its ActivityPub actor id
+/// (e.g. "https://bsky.brid.gy/ap/did:plc:abc") or its profile URL (e.g. "https://bsky.app/profile/did:plc:abc")
+pub fn did_from_url(url: &str) -> Option<Did> {
+    url.split(['/', '?', '#'])
+        .find(|segment| segment.starts_with("did:"))
+        .and_then(|segment| Did::new(segment.to_string()).ok())
+}
+
+/// Get the handle that a fediverse account gets on Bluesky when it is bridged by Bridgy Fed
+/// (e.g. "user_name@mastodon.social" gives "user-name.mastodon.social.ap.brid.gy").
+///
+/// `local_domain` is used for accounts of the user's own server whose address doesn't include a domain.
+pub fn mastodon_acct_to_bridged_bluesky_handle(acct: &str, local_domain: &str) -> String {
+    let acct = acct.trim_start_matches('@');
+    let (username, domain) = acct.rsplit_once('@').unwrap_or((acct, local_domain));
+    // Characters that are valid in fediverse usernames but not in Bluesky handles are replaced by Bridgy Fed
+    let username = username.replace(['_', '~'], "-");
+    format!("{username}.{domain}.{BRIDGY_BLUESKY_DOMAIN}").to_lowercase()
+}
+
+/// Replace a file with one written by `write` next to it, so that an interrupted write leaves the previous file intact
+pub fn replace_file(path: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let written = write(&temp_path).and_then(|()| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Write a file atomically, see [`replace_file`]
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    replace_file(path, |temp_path| {
+        let mut file = fs::File::create(temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()
+    })
+}
+
+/// Format a timestamp in the local time zone for display
+pub fn format_timestamp(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Parse a point in time given by the user, either as a timestamp (e.g. "2025-01-02T10:00:00Z"), a date
+/// (e.g. "2025-01-02", meaning the end of that day) or a duration in the past (e.g. "1 week" or "3d ago").
+pub fn parse_point_in_time(input: &str) -> Result<Timestamp> {
+    let input = input.trim();
+
+    if let Ok(timestamp) = input.parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+
+    if let Ok(date) = input.parse::<civil::Date>() {
+        let end_of_day = date.at(23, 59, 59, 0).to_zoned(TimeZone::system())?;
+        return Ok(end_of_day.timestamp());
+    }
+
+    let span: Span = input
+        .trim_end_matches("ago")
+        .trim()
+        .parse()
+        .map_err(|_| eyre!("Invalid point in time '{input}', expected a timestamp, a date or a duration like '1 week'"))?;
+    Ok(Zoned::now().checked_sub(span)?.timestamp())
+}
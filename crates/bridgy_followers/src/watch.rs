@@ -0,0 +1,55 @@
+use color_eyre::Result;
+use rand::Rng;
+use std::time::Duration;
+
+/// Resolves when the process is asked to stop, either by SIGTERM (e.g. from a service manager) or Ctrl+C.
+///
+/// The handlers are registered on creation so a signal received while a sync cycle is running isn't lost and is
+/// handled once the cycle completes.
+pub struct ShutdownSignal {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    ctrl_c: tokio::signal::windows::CtrlC,
+}
+
+impl ShutdownSignal {
+    #[cfg(unix)]
+    pub fn new() -> Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            ctrl_c: tokio::signal::windows::ctrl_c()?,
+        })
+    }
+
+    #[cfg(unix)]
+    pub async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.terminate.recv() => {}
+            _ = self.interrupt.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    pub async fn recv(&mut self) {
+        self.ctrl_c.recv().await;
+    }
+}
+
+/// Delay before the next cycle, the interval plus a random jitter so that multiple instances don't hit the servers
+/// at the same time
+pub fn next_delay(interval: Duration, max_jitter: Duration) -> Duration {
+    let jitter_secs = rand::rng().random_range(0..=max_jitter.as_secs());
+    interval + Duration::from_secs(jitter_secs)
+}
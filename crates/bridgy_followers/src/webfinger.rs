@@ -1,6 +1,30 @@
-use color_eyre::eyre::Result;
-use reqwest::Url;
-use tracing::{debug, instrument};
+use color_eyre::eyre::{Result, WrapErr, bail};
+use reqwest::{Client, StatusCode, Url, header::RETRY_AFTER};
+use std::time::Duration;
+use tracing::{debug, instrument, warn};
+
+const USER_AGENT: &str = "bridgy_followers";
+
+/// Number of times a lookup is retried after a timeout, a rate limit or a server error
+const MAX_RETRIES: u32 = 2;
+
+/// Initial delay before retrying a lookup, doubled on each attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest `Retry-After` delay waited for, a longer one fails the lookup
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Create the HTTP client used for WebFinger lookups.
+///
+/// The client keeps a pool of connections so it should be created once and shared between lookups.
+pub fn create_client(timeout: Duration, max_connections: usize) -> Result<Client> {
+    Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(timeout)
+        .pool_max_idle_per_host(max_connections)
+        .build()
+        .wrap_err("Failed to create WebFinger HTTP client")
+}
 
 /// Construct the WebFinger URL for a given domain and resource according to [RFC 7033][rfc7033].
 ///
@@ -19,15 +43,169 @@ fn get_domain_webfinger_url(domain: &str, resource: &str) -> Result<Url, url::Pa
 /// Uses WebFinger (RFC [7565][rfc7565]) to check if an account exists on a given
 /// domain using its acct URI.
 ///
+/// Only a 404 means that the account doesn't exist. Timeouts, rate limits and server errors are retried, and fail the
+/// lookup if they persist.
+///
 /// [rfc7565]: https://datatracker.ietf.org/doc/html/rfc7565
-#[instrument()]
-pub async fn account_exists(domain: &str, acct: &str) -> Result<bool> {
+#[instrument(skip(client))]
+pub async fn account_exists(client: &Client, domain: &str, acct: &str) -> Result<bool> {
+    lookup_with_retries(client, domain, acct, INITIAL_RETRY_DELAY).await
+}
+
+async fn lookup_with_retries(
+    client: &Client,
+    domain: &str,
+    acct: &str,
+    initial_retry_delay: Duration,
+) -> Result<bool> {
     let resource = format!("acct:{}", acct);
     let url = get_domain_webfinger_url(domain, &resource)?;
 
-    debug!("Fetching WebFinger URL: {}", url);
-    let response = reqwest::get(url).await?;
-    let status = response.status();
+    let mut attempt = 0;
+    loop {
+        debug!("Fetching WebFinger URL: {}", url);
+        let (retry_after, error) = match client.get(url.clone()).send().await {
+            Ok(response) => match response.status() {
+                status if status.is_success() => return Ok(true),
+                StatusCode::NOT_FOUND => return Ok(false),
+                status @ StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+                        .map(Duration::from_secs);
+                    (retry_after, format!("status {status}"))
+                }
+                status if status.is_server_error() => (None, format!("status {status}")),
+                status => bail!("WebFinger lookup failed for {acct}: status {status}"),
+            },
+            Err(e) if e.is_timeout() || e.is_connect() => (None, e.to_string()),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| format!("WebFinger lookup failed for {acct}"));
+            }
+        };
+
+        let delay = retry_after.unwrap_or(initial_retry_delay * 2u32.pow(attempt));
+        if attempt >= MAX_RETRIES || delay > MAX_RETRY_AFTER {
+            bail!(
+                "WebFinger lookup failed for {acct} after {} attempt(s): {error}",
+                attempt + 1
+            );
+        }
+        attempt += 1;
+        warn!(
+            attempt,
+            "WebFinger lookup failed for {acct}, retrying in {}s: {error}",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Response};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    const ACCT: &str = "user.bsky.social@bsky.brid.gy";
+    const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+    /// Start a stub server answering with the response for the number of the request, counting the requests
+    async fn server(
+        respond: impl Fn(u32) -> Option<Response> + Send + Sync + 'static,
+    ) -> (String, Arc<AtomicU32>) {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let server_url = test_server::start(move |request| {
+            assert_eq!(request.path(), "/.well-known/webfinger");
+            assert_eq!(
+                request.target,
+                "/.well-known/webfinger?resource=acct%3Auser.bsky.social%40bsky.brid.gy"
+            );
+            respond(counter.fetch_add(1, Ordering::SeqCst))
+        })
+        .await;
+        (server_url, requests)
+    }
+
+    fn client() -> Client {
+        create_client(Duration::from_millis(200), 1).unwrap()
+    }
+
+    #[tokio::test]
+    async fn found() {
+        let (server_url, requests) = server(|_| Some(Response::new(200))).await;
+        let exists = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(exists.unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn not_found() {
+        let (server_url, requests) = server(|_| Some(Response::new(404))).await;
+        let exists = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(!exists.unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_is_retried() {
+        let (server_url, requests) = server(|request| {
+            Some(if request == 0 {
+                Response::new(429)
+            } else {
+                Response::new(200)
+            })
+        })
+        .await;
+        let exists = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(exists.unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_fails_after_retries() {
+        let (server_url, requests) = server(|_| Some(Response::new(429))).await;
+        let error = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("429"));
+        assert_eq!(requests.load(Ordering::SeqCst), MAX_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn long_retry_after_fails() {
+        let (server_url, requests) =
+            server(|_| Some(Response::new(429).header("Retry-After", "3600"))).await;
+        let result = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn server_error_is_retried() {
+        let (server_url, requests) = server(|request| {
+            Some(if request == 0 {
+                Response::new(502)
+            } else {
+                Response::new(404)
+            })
+        })
+        .await;
+        let exists = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(!exists.unwrap());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
 
-    Ok(status.is_success())
+    #[tokio::test]
+    async fn timeout_fails_after_retries() {
+        let (server_url, requests) = server(|_| None).await;
+        let result = lookup_with_retries(&client(), &server_url, ACCT, RETRY_DELAY).await;
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), MAX_RETRIES + 1);
+    }
 }